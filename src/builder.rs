@@ -1,21 +1,39 @@
+use super::cache::{CacheEntry, IndexCache};
 #[cfg(feature = "emacs")]
 use super::emacs::{EMACS_CHARSET_MAP, SCRIPT_REPRESENTATIVE_CHARS};
+#[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
+use super::fc_cache::{CachedFont, FcCaches};
+#[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
+use super::fontconfig::{self, SelectRules};
 use super::index::*;
 use super::index_data::*;
 use super::library::FontLibrary;
+use super::report::{ScanOutcome, ScanReport, SourceReport};
+use super::shared_data::SharedData;
+#[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
+use super::substitute::{self, Substitutions};
 use super::system::{Os, OS};
 use super::types::*;
+use crate::util::fxhash::{FxHashMap, FxHashSet};
+use crate::util::glob::Glob;
 use crate::util::string::SmallString;
 use std::{
+    borrow::Cow,
     fs,
+    io::{self, Read},
     path::{Path, PathBuf},
-    sync::RwLock,
+    sync::atomic::{AtomicUsize, Ordering},
+    sync::{Arc, RwLock},
+    thread,
     time::SystemTime,
 };
 #[cfg(feature = "emacs")]
 use swash::text::Language;
 use swash::Tag;
-use swash::{Attributes, CacheKey, FontDataRef, FontRef, Stretch, StringId, Style, Weight};
+use swash::{
+    Attributes, CacheKey, FontDataRef, FontRef, NormalizedCoord, Stretch, StringId, Style,
+    TableProvider, Weight,
+};
 
 /// Hint for specifying whether font files should be memory mapped.
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
@@ -35,14 +53,51 @@ impl Default for MmapHint {
     }
 }
 
+/// Policy for resolving fonts that have the same family and attributes as
+/// a font that is already in the library.
+///
+/// The policy applies to fonts in the same layer, since fonts in a higher
+/// layer always override those in lower layers. Fonts that are not selected
+/// are kept as shadowed fonts of their family and are not matched by
+/// queries.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub enum DuplicatePolicy {
+    /// Keep the font that was added first.
+    #[default]
+    First,
+    /// Prefer fonts in the user's home directory over other fonts. Otherwise
+    /// keep the font that was added first.
+    PreferUser,
+    /// Prefer the font with the highest revision. Otherwise keep the font
+    /// that was added first.
+    PreferNewer,
+    /// Keep all fonts. Queries match the font that was added first.
+    KeepAll,
+}
+
+/// Font source added to a builder. These are recorded so that the library
+/// can be rescanned when it is refreshed.
+#[derive(Clone)]
+enum Input {
+    Dir(PathBuf),
+    File(PathBuf),
+    Data(SharedData),
+}
+
 /// Builder for configuring a font library.
 #[derive(Default)]
 pub struct FontLibraryBuilder {
     inner: Inner,
     scanner: Scanner,
+    inputs: Vec<(SourceLayer, Input)>,
+    cache_path: Option<PathBuf>,
     all_names: bool,
     generics: bool,
     fallbacks: bool,
+    #[cfg(all(feature = "watch", target_os = "linux"))]
+    watch: bool,
+    #[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
+    fontconfig: Option<fontconfig_parser::FontConfig>,
 }
 
 impl FontLibraryBuilder {
@@ -59,21 +114,199 @@ impl FontLibraryBuilder {
         self
     }
 
+    /// Specifies a file for caching the results of scanning font files.
+    ///
+    /// Files added after this call that are unchanged since the cache was
+    /// written are loaded from the cache rather than parsed. The cache is
+    /// rewritten when the library is built.
+    pub fn cache_file(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.scanner.cache = Some(IndexCache::load(&path));
+        self.cache_path = Some(path.as_ref().into());
+        self
+    }
+
+    /// Specifies how fonts with the same family and attributes as a font
+    /// that was added before them are resolved. The default is
+    /// [`DuplicatePolicy::First`].
+    pub fn duplicate_policy(&mut self, policy: DuplicatePolicy) -> &mut Self {
+        self.inner.duplicates = policy;
+        self
+    }
+
+    /// Specifies the home directory that holds user fonts instead of
+    /// reading it from the environment.
+    #[cfg(test)]
+    fn home_dir(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.inner.home = Some(path.as_ref().into());
+        self
+    }
+
+    /// Specifies whether the library should watch its font directories and
+    /// refresh itself when fonts are added, removed or modified.
+    ///
+    /// On refresh, only the directories that changed are scanned again.
+    /// Scan results of fonts added after this call are kept in memory so
+    /// that files in other directories are not parsed again.
+    #[cfg(all(feature = "watch", target_os = "linux"))]
+    pub fn watch(&mut self, yes: bool) -> &mut Self {
+        self.watch = yes;
+        if yes {
+            self.scanner.cache.get_or_insert_with(IndexCache::default);
+        }
+        self
+    }
+
+    /// Specifies whether directories with a current fontconfig cache are
+    /// indexed from the cache rather than by parsing their font files.
+    /// Directories without a cache, or whose cache is stale, are scanned as
+    /// usual.
+    ///
+    /// Fonts indexed from fontconfig caches have no writing system
+    /// information since the caches only record character sets. They never
+    /// match requests for OpenType scripts and features, such as the `:otf`
+    /// property of Emacs font specs.
+    #[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
+    pub fn fontconfig_cache(&mut self, yes: bool) -> &mut Self {
+        self.scanner.fc_cache = yes;
+        self
+    }
+
+    /// Specifies the number of threads used to parse font files in
+    /// directories. A value of zero selects the available parallelism of
+    /// the system. The default is one.
+    pub fn threads(&mut self, count: usize) -> &mut Self {
+        self.scanner.threads = if count == 0 {
+            thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1)
+        } else {
+            count
+        };
+        self
+    }
+
+    /// Specifies the file extensions of fonts that are added from
+    /// directories, ignoring case. The default accepts ttf, otf, ttc, otc
+    /// and dfont files, along with woff and woff2 files when the `woff`
+    /// feature is enabled.
+    pub fn extensions<I>(&mut self, extensions: I) -> &mut Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.scanner.extensions = Some(extensions.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Specifies whether files in directories that do not have an accepted
+    /// extension should be opened and added if they begin with a font
+    /// signature. This finds fonts with unusual or missing extensions at
+    /// the cost of reading every file.
+    pub fn sniff_content(&mut self, yes: bool) -> &mut Self {
+        self.scanner.sniff = yes;
+        self
+    }
+
+    /// Specifies the maximum depth of subdirectories that are scanned below
+    /// each added directory. The default is 4.
+    pub fn max_depth(&mut self, depth: u32) -> &mut Self {
+        self.scanner.traversal.max_depth = depth;
+        self
+    }
+
+    /// Specifies whether symbolic links to files and directories should be
+    /// followed when scanning directories. The default is true. Files and
+    /// directories reached through more than one path are only scanned
+    /// once.
+    pub fn follow_symlinks(&mut self, yes: bool) -> &mut Self {
+        self.scanner.traversal.follow_symlinks = yes;
+        self
+    }
+
+    /// Specifies whether subdirectories with names starting with a dot
+    /// should be skipped when scanning directories. The default is false.
+    pub fn skip_hidden(&mut self, yes: bool) -> &mut Self {
+        self.scanner.traversal.skip_hidden = yes;
+        self
+    }
+
+    /// Adds a glob pattern for files that should be added when scanning
+    /// directories. When any include patterns are specified, only files that
+    /// match at least one of them are added.
+    ///
+    /// Patterns without a path separator, such as `*.otf`, match the file
+    /// name. Other patterns match the full path, where `*` does not cross
+    /// separators and `**` does.
+    pub fn include(&mut self, pattern: impl AsRef<str>) -> &mut Self {
+        self.scanner.include.push(Glob::new(pattern.as_ref()));
+        self
+    }
+
+    /// Adds a glob pattern for files and directories that should be skipped
+    /// when scanning directories, such as `/usr/share/fonts/X11/**`. This
+    /// also applies to font directories from the fontconfig configuration.
+    /// Exclusions take precedence over inclusions.
+    pub fn exclude(&mut self, pattern: impl AsRef<str>) -> &mut Self {
+        self.scanner.exclude.push(Glob::new(pattern.as_ref()));
+        self
+    }
+
+    /// Specifies the layer of sources added after this call. Families and
+    /// fonts in a higher layer override those in lower layers when
+    /// querying the library. The default is [`SourceLayer::App`].
+    ///
+    /// System and user fonts are always added to the corresponding layer.
+    pub fn layer(&mut self, layer: SourceLayer) -> &mut Self {
+        self.inner.layer = layer;
+        self
+    }
+
+    /// Specifies whether a family in a higher layer overrides all fonts of
+    /// the family in lower layers. By default, a font only overrides fonts
+    /// of its family in lower layers with the same stretch, weight and
+    /// style.
+    pub fn override_families(&mut self, yes: bool) -> &mut Self {
+        self.inner.override_families = yes;
+        self
+    }
+
     /// Adds fonts from the specified directory to the library.
     pub fn add_dir(&mut self, path: impl AsRef<Path>) -> &mut Self {
-        self.scanner.scan_dir(path, self.all_names, &mut self.inner);
+        self.prepare();
+        self.inputs
+            .push((self.inner.layer, Input::Dir(path.as_ref().into())));
+        self.scan_dir(path);
         self
     }
 
     /// Adds a font file to the library.
     pub fn add_file(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.prepare();
+        self.inputs
+            .push((self.inner.layer, Input::File(path.as_ref().into())));
         self.scanner
             .scan_file(path, self.all_names, &mut self.inner);
         self
     }
 
+    /// Adds a font from the specified bytes to the library.
+    pub fn add_data(&mut self, data: Vec<u8>) -> &mut Self {
+        self.add_shared_data(SharedData::new(data))
+    }
+
+    /// Adds a font from the specified shared data to the library.
+    pub fn add_shared_data(&mut self, data: SharedData) -> &mut Self {
+        self.prepare();
+        self.inputs
+            .push((self.inner.layer, Input::Data(data.clone())));
+        self.scanner
+            .scan_shared_data(data, self.all_names, &mut self.inner);
+        self
+    }
+
     /// Adds system fonts to the library.
     pub fn add_system_fonts(&mut self) -> &mut Self {
+        let layer = core::mem::replace(&mut self.inner.layer, SourceLayer::System);
         match OS {
             Os::Windows => {
                 if let Some(mut windir) = std::env::var_os("SYSTEMROOT") {
@@ -100,11 +333,13 @@ impl FontLibraryBuilder {
             }
             Os::Other => {}
         }
+        self.inner.layer = layer;
         self
     }
 
     /// Adds user fonts to the library.
     pub fn add_user_fonts(&mut self) -> &mut Self {
+        let layer = core::mem::replace(&mut self.inner.layer, SourceLayer::User);
         match OS {
             Os::Windows => {}
             Os::MacOs => {
@@ -123,6 +358,7 @@ impl FontLibraryBuilder {
             }
             Os::Other => {}
         }
+        self.inner.layer = layer;
         self
     }
 
@@ -142,29 +378,181 @@ impl FontLibraryBuilder {
 
     /// Builds a library for the current configuration.
     pub fn build(&mut self) -> FontLibrary {
+        self.build_with_report().0
+    }
+
+    /// Builds a library for the current configuration and returns it along
+    /// with the outcome of scanning each font source.
+    pub fn build_with_report(&mut self) -> (FontLibrary, ScanReport) {
+        let index = self.build_index();
+        let report = core::mem::take(&mut self.inner.report);
+        let mut config = self.options();
+        config.inputs = self.inputs.clone();
+        // Scan results are only needed in memory to refresh a watched
+        // library. Other libraries reload the cache file on refresh.
+        #[cfg(all(feature = "watch", target_os = "linux"))]
+        {
+            if self.watch {
+                config.scanner.cache = self.scanner.cache.take();
+                config.scanner.dirs = core::mem::take(&mut self.scanner.dirs);
+            }
+        }
+        (FontLibrary::new(index, config), report)
+    }
+
+    /// Builds a new index by rescanning all sources that were added to this
+    /// builder. Files that are unchanged since the last scan are loaded
+    /// from the in-memory scan results of a watched library or from the
+    /// cache file, if present.
+    pub(super) fn rebuild(&mut self) -> StaticIndex {
+        self.rebuild_with(None)
+    }
+
+    /// Builds a new index as with [`rebuild`](Self::rebuild), but only walks
+    /// the specified directories again. The contents of other directories
+    /// are taken from the last scan and their files from the in-memory scan
+    /// results.
+    #[cfg(all(feature = "watch", target_os = "linux"))]
+    pub(super) fn rebuild_dirs(&mut self, changed: &FxHashSet<PathBuf>) -> StaticIndex {
+        self.rebuild_with(Some(changed))
+    }
+
+    fn rebuild_with(&mut self, changed: Option<&FxHashSet<PathBuf>>) -> StaticIndex {
+        let mut builder = self.options();
+        builder.scanner.cache = match self.scanner.cache.take() {
+            Some(mut cache) => {
+                cache.reset();
+                Some(cache)
+            }
+            None => self.cache_path.as_ref().map(IndexCache::load),
+        };
+        if let Some(changed) = changed {
+            builder.scanner.reuse = self
+                .scanner
+                .dirs
+                .drain(..)
+                .filter(|dir| dir.entries.is_some() && !changed.contains(&dir.path))
+                .map(|dir| (dir.path.clone(), dir))
+                .collect();
+        }
+        for (layer, input) in &self.inputs {
+            builder.layer(*layer);
+            match input {
+                Input::Dir(path) => builder.add_dir(path),
+                Input::File(path) => builder.add_file(path),
+                Input::Data(data) => builder.add_shared_data(data.clone()),
+            };
+        }
+        let index = builder.build_index();
+        self.scanner.cache = builder.scanner.cache.take();
+        self.scanner.dirs = core::mem::take(&mut builder.scanner.dirs);
+        index
+    }
+
+    /// Returns the directories that were scanned during the last build.
+    #[cfg(all(feature = "watch", target_os = "linux"))]
+    pub(super) fn scanned_dirs(&self) -> impl Iterator<Item = &PathBuf> {
+        self.scanner.dirs.iter().map(|dir| &dir.path)
+    }
+
+    /// Returns true if the library should watch its font directories.
+    #[cfg(all(feature = "watch", target_os = "linux"))]
+    pub(super) fn watching(&self) -> bool {
+        self.watch
+    }
+
+    /// Returns a new builder with the same options and no sources.
+    fn options(&self) -> Self {
+        let mut builder = Self::default();
+        builder.inner.mmap_hint = self.inner.mmap_hint;
+        builder.inner.duplicates = self.inner.duplicates;
+        builder.inner.override_families = self.inner.override_families;
+        builder.inner.home = self.inner.home.clone();
+        builder.cache_path = self.cache_path.clone();
+        builder.all_names = self.all_names;
+        builder.generics = self.generics;
+        builder.fallbacks = self.fallbacks;
+        builder.scanner.threads = self.scanner.threads;
+        builder.scanner.extensions = self.scanner.extensions.clone();
+        builder.scanner.sniff = self.scanner.sniff;
+        builder.scanner.traversal = self.scanner.traversal;
+        builder.scanner.include = self.scanner.include.clone();
+        builder.scanner.exclude = self.scanner.exclude.clone();
+        #[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
+        {
+            builder.scanner.fc_cache = self.scanner.fc_cache;
+        }
+        #[cfg(all(feature = "watch", target_os = "linux"))]
+        builder.watch(self.watch);
+        builder
+    }
+
+    /// Loads the fontconfig configuration, and its caches when enabled,
+    /// before the first scan so that its font selection rules apply to all
+    /// sources.
+    fn prepare(&mut self) {
+        #[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
+        {
+            if self.fontconfig.is_none() {
+                let fontconfig = load_fontconfig();
+                self.inner.select = SelectRules::new(&fontconfig.select_fonts);
+                self.fontconfig = Some(fontconfig);
+            }
+            if self.scanner.fc_cache && self.scanner.fc_caches.is_none() {
+                let cache_dirs = self
+                    .fontconfig
+                    .as_ref()
+                    .map(|fontconfig| fontconfig_cache_dirs(&fontconfig.cache_dirs))
+                    .unwrap_or_default();
+                self.scanner.fc_caches = Some(FcCaches::load(&cache_dirs));
+            }
+        }
+    }
+
+    fn scan_dir(&mut self, path: impl AsRef<Path>) {
+        self.scanner.scan_dir(path, self.all_names, &mut self.inner);
+    }
+
+    fn build_index(&mut self) -> StaticIndex {
+        self.prepare();
         #[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
-        let fontconfig = load_fontconfig();
+        let fontconfig = self.fontconfig.take().unwrap_or_default();
         #[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
         {
-            let home = std::env::var("HOME");
+            let home = self.inner.home.clone();
+            let layer = self.inner.layer;
             fontconfig.dirs.iter().for_each(|dir| {
-                if dir.path.starts_with("~") {
-                    if let Ok(ref home) = home {
-                        let path = Path::new(home).join(dir.path.strip_prefix("~").unwrap());
-                        self.add_dir(path);
+                let path = if dir.path.starts_with("~") {
+                    match home {
+                        Some(ref home) => home.join(dir.path.strip_prefix("~").unwrap()),
+                        None => return,
                     }
                 } else {
-                    self.add_dir(dir.clone().path);
+                    dir.path.clone()
+                };
+                self.inner.layer = if is_user_path(home.as_deref(), &path) {
+                    SourceLayer::User
+                } else {
+                    SourceLayer::System
                 };
+                self.scan_dir(path);
             });
+            self.inner.layer = layer;
+        }
+        if let (Some(path), Some(cache)) = (&self.cache_path, &self.scanner.cache) {
+            if let Err(err) = cache.save(path) {
+                log::warn!("failed to write font index cache {:?}: {}", path, err);
+            }
         }
         let mut index = StaticIndex::default();
         core::mem::swap(&mut index, &mut self.inner.index);
         for family in index.families.iter_mut() {
-            family
-                .fonts
-                .sort_unstable_by(|a, b| a.weight.cmp(&b.weight));
+            // Fonts with the same weight stay in the order they were added so
+            // that queries match the first of several duplicates.
+            family.fonts.sort_by_key(|font| font.weight);
         }
+        map_family_aliases(&mut index, core::mem::take(&mut self.inner.aliases));
+        index.setup_coverage_blocks();
         if self.generics {
             index.setup_default_generic();
         }
@@ -177,49 +565,75 @@ impl FontLibraryBuilder {
             use fontconfig_parser::Alias;
 
             if self.generics {
-                fontconfig.aliases.iter().for_each(
-                    |Alias {
-                         alias,
-                         default,
-                         prefer,
-                         accept,
-                     }| {
-                        let names = [&prefer[..], &accept[..], &default[..]].concat();
-                        let find_family = |families: Vec<String>| {
-                            for family in families {
-                                if let Some(id) = index
-                                    .base
-                                    .family_map
-                                    .get(family.trim().to_lowercase().as_str())
-                                {
-                                    return Some(*id);
-                                }
+                // Aliases behave like fontconfig substitutions: preferred
+                // families are inserted before the generic name, accepted
+                // families directly after it and default families at the
+                // end.
+                let mut lists: [(Vec<_>, Vec<_>, Vec<_>); GenericFamily::COUNT] =
+                    Default::default();
+                for Alias {
+                    alias,
+                    default,
+                    prefer,
+                    accept,
+                } in &fontconfig.aliases
+                {
+                    if let Some(generic_family) = GenericFamily::parse(alias) {
+                        let (prefers, accepts, defaults) = &mut lists[generic_family as usize];
+                        prefers.extend(prefer);
+                        accepts.splice(0..0, accept);
+                        defaults.extend(default);
+                    }
+                }
+                for (i, (prefers, accepts, defaults)) in lists.iter().enumerate() {
+                    let chain = &mut index.generic_chains[i];
+                    for name in prefers.iter().chain(accepts).chain(defaults) {
+                        if let Some(id) = index
+                            .base
+                            .family_map
+                            .get(name.trim().to_lowercase().as_str())
+                        {
+                            if !chain.contains(id) {
+                                chain.push(*id);
                             }
-                            None
-                        };
-                        if let Some(generic_family) = GenericFamily::parse(alias) {
-                            if let Some(id) = find_family(names.clone()) {
-                                index.generic[generic_family as usize] = Some(id);
-                            };
                         }
-                    },
-                )
+                    }
+                    if let Some(id) = chain.first() {
+                        index.generic[i] = Some(*id);
+                    }
+                }
             }
+            index.substitutions = Substitutions::new(
+                fontconfig::substitution_rules(&fontconfig),
+                substitute::locale_lang(),
+            );
         }
 
-        FontLibrary::new(index)
+        index
     }
 }
 
 struct Inner {
     path: PathBuf,
+    data: Option<SharedData>,
     mmap: bool,
     timestamp: SystemTime,
     source: SourceId,
     file_added: bool,
     mmap_hint: MmapHint,
+    duplicates: DuplicatePolicy,
+    layer: SourceLayer,
+    override_families: bool,
     index: StaticIndex,
+    report: ScanReport,
     lowercase_name: String,
+    /// Alternate family names of fonts with the family and fonts that have
+    /// them.
+    aliases: FxHashMap<SmallString, (FamilyId, Vec<FontId>)>,
+    #[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
+    select: SelectRules,
+    /// Home directory of the user, which holds user fonts.
+    home: Option<PathBuf>,
 }
 
 impl Default for Inner {
@@ -232,13 +646,24 @@ impl Inner {
     fn new() -> Self {
         Self {
             path: PathBuf::new(),
+            data: None,
             mmap: false,
             timestamp: SystemTime::UNIX_EPOCH,
             source: SourceId(0),
             file_added: false,
             mmap_hint: MmapHint::default(),
+            duplicates: DuplicatePolicy::default(),
+            layer: SourceLayer::default(),
+            override_families: false,
             index: StaticIndex::default(),
+            report: ScanReport::default(),
             lowercase_name: String::default(),
+            aliases: FxHashMap::default(),
+            #[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
+            select: SelectRules::default(),
+            home: std::env::var_os("HOME")
+                .filter(|home| !home.is_empty())
+                .map(PathBuf::from),
         }
     }
 }
@@ -250,52 +675,137 @@ impl ScannerSink for Inner {
             MmapHint::Always => true,
             MmapHint::Threshold(value) => (value as u64) < size,
         };
+        self.report.sources.push(SourceReport {
+            path: Some(path.clone()),
+            outcomes: Vec::new(),
+        });
         self.path = path;
+        self.data = None;
         self.mmap = mmap;
         self.timestamp = timestamp;
         self.source = SourceId(self.index.base.sources.len() as u32);
         self.file_added = false;
     }
 
+    fn enter_data(&mut self, data: SharedData) {
+        self.report.sources.push(SourceReport {
+            path: None,
+            outcomes: Vec::new(),
+        });
+        self.path = PathBuf::new();
+        self.data = Some(data);
+        self.mmap = false;
+        self.timestamp = SystemTime::UNIX_EPOCH;
+        self.source = SourceId(self.index.base.sources.len() as u32);
+        self.file_added = false;
+    }
+
     fn add_font(&mut self, font: &FontInfo) {
+        #[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
+        {
+            let path = Some(self.path.as_path()).filter(|path| !path.as_os_str().is_empty());
+            if !self.select.accepts(path, font) {
+                self.report.add(ScanOutcome::Rejected { index: font.index });
+                return;
+            }
+        }
         self.lowercase_name.clear();
         self.lowercase_name
             .extend(font.name.chars().map(|c| c.to_lowercase()).flatten());
         let index = &mut self.index;
-        let family =
-            if let Some(family_id) = index.base.family_map.get(self.lowercase_name.as_str()) {
-                let family = &mut index.families[family_id.to_usize()];
-                if family.contains(font.stretch, font.weight, font.style) {
-                    return;
+        // The font with the same attributes that the new font replaces or is
+        // shadowed by.
+        let mut duplicate = None;
+        let mut overridden = false;
+        let mut replaced = false;
+        // Fonts that the new font shadows, whose names must no longer
+        // resolve to them.
+        let mut unmapped = Vec::new();
+        let family = if let Some(family_id) =
+            index.base.family_map.get(self.lowercase_name.as_str())
+        {
+            let family = &mut index.families[family_id.to_usize()];
+            if self.override_families && self.layer != family.layer {
+                if self.layer > family.layer {
+                    // The family in this layer replaces the fonts from lower
+                    // layers.
+                    unmapped.extend(family.fonts.iter().map(|font| font.id));
+                    family
+                        .shadowed
+                        .extend(family.fonts.drain(..).map(|font| font.id));
+                    replaced = true;
+                } else {
+                    overridden = true;
                 }
-                family
             } else {
-                let family_id = FamilyId(index.families.len() as u32);
-                let family = FamilyData {
-                    id: family_id,
-                    name: SmallString::new(&font.name),
-                    fonts: Vec::new(),
-                    has_stretch: true,
-                };
-                index.families.push(family);
-                index
-                    .base
-                    .family_map
-                    .insert(SmallString::new(&self.lowercase_name), family_id);
-                &mut index.families[family_id.to_usize()]
+                // Named instances do not duplicate their own variable font.
+                let (fonts, sources, source) =
+                    (&index.base.fonts, &index.base.sources, self.source);
+                let existing = family
+                    .find(font.stretch, font.weight, font.style)
+                    .filter(|id| {
+                        let data = &fonts[id.to_usize()];
+                        data.source != source || data.offset != font.offset
+                    });
+                if let Some(existing) = existing {
+                    let data = &fonts[existing.to_usize()];
+                    let layer = sources[data.source.to_usize()].layer;
+                    if self.layer > layer {
+                        // The font in this layer replaces the font with the
+                        // same attributes from a lower layer.
+                        duplicate = Some((existing, true));
+                    } else if self.layer < layer {
+                        overridden = true;
+                    } else if self.duplicates != DuplicatePolicy::KeepAll {
+                        let replace = match self.duplicates {
+                            DuplicatePolicy::PreferUser => {
+                                let source = &sources[data.source.to_usize()];
+                                let home = self.home.as_deref();
+                                is_user_path(home, &self.path)
+                                    && !source.path().is_some_and(|path| is_user_path(home, path))
+                            }
+                            DuplicatePolicy::PreferNewer => font.revision > data.revision,
+                            _ => false,
+                        };
+                        duplicate = Some((existing, replace));
+                    }
+                }
+            }
+            family.layer = family.layer.max(self.layer);
+            family
+        } else {
+            let family_id = FamilyId(index.families.len() as u32);
+            let family = FamilyData {
+                id: family_id,
+                name: SmallString::new(&font.name),
+                fonts: Vec::new(),
+                shadowed: Vec::new(),
+                layer: self.layer,
+                has_stretch: true,
             };
+            index.families.push(family);
+            index
+                .base
+                .family_map
+                .insert(SmallString::new(&self.lowercase_name), family_id);
+            &mut index.families[family_id.to_usize()]
+        };
         if !self.file_added {
             self.file_added = true;
-            let mut path2 = PathBuf::new();
-            core::mem::swap(&mut path2, &mut self.path);
-            index.base.sources.push(SourceData {
-                id: self.source,
-                kind: SourceKind::File(FileData {
-                    path: path2.into(),
+            let kind = if let Some(data) = self.data.take() {
+                SourceKind::Memory(data)
+            } else {
+                SourceKind::File(FileData {
+                    path: self.path.clone().into(),
                     mmap: self.mmap,
                     timestamp: self.timestamp,
                     status: RwLock::new(FileDataStatus::Empty),
-                }),
+                })
+            };
+            index.base.sources.push(SourceData {
+                id: self.source,
+                layer: self.layer,
+                kind,
             });
         }
         let font_id = FontId(index.base.fonts.len() as u32);
@@ -307,15 +817,60 @@ impl ScannerSink for Inner {
             index: font.index,
             offset: font.offset,
             attributes: font.attrs,
+            revision: font.revision,
+            axes: font.axes.clone(),
+            subfamily: SmallString::new(&font.subfamily),
+            instance: font.instance,
+            coords: font.coords.clone(),
+            spacing: font.spacing,
+            foundry: SmallString::new(&font.foundry),
+            coverage: font.coverage.clone(),
+            color: font.color,
             key: CacheKey::new(),
         };
         index.base.fonts.push(font_data);
-        family.fonts.push(FamilyFontData {
+        let family_font = FamilyFontData {
             id: font_id,
             stretch: font.stretch,
             weight: font.weight,
             style: font.style,
             writing_systems: font.writing_systems.clone(),
+            axes: font.axes.clone(),
+            spacing: font.spacing,
+            foundry: SmallString::new(&font.foundry),
+            color: font.color,
+        };
+        if overridden {
+            family.shadowed.push(font_id);
+            self.report.add(ScanOutcome::Overridden {
+                index: font.index,
+                id: font_id,
+            });
+            return;
+        }
+        match duplicate {
+            Some((existing, false)) => {
+                family.shadowed.push(font_id);
+                self.report.add(ScanOutcome::Duplicate {
+                    index: font.index,
+                    id: font_id,
+                    existing,
+                });
+                return;
+            }
+            Some((existing, true)) => {
+                if let Some(slot) = family.fonts.iter_mut().find(|f| f.id == existing) {
+                    *slot = family_font;
+                }
+                family.shadowed.push(existing);
+                unmapped.push(existing);
+                replaced = true;
+            }
+            None => family.fonts.push(family_font),
+        }
+        self.report.add(ScanOutcome::Added {
+            index: font.index,
+            id: font_id,
         });
         if font.stretch != Stretch::NORMAL {
             family.has_stretch = true;
@@ -328,6 +883,33 @@ impl ScannerSink for Inner {
                     .insert(SmallString::new(name.as_str()), family_id);
             }
         }
+        for alias in &font.aliases {
+            let (alias_family, fonts) = self
+                .aliases
+                .entry(SmallString::new(alias))
+                .or_insert_with(|| (family_id, Vec::new()));
+            if *alias_family == family_id {
+                fonts.push(font_id);
+            }
+        }
+        if !unmapped.is_empty() {
+            let maps = [
+                &mut index.base.postscript_map,
+                &mut index.base.full_name_map,
+            ];
+            for map in maps {
+                map.retain(|_, id| !unmapped.contains(id));
+            }
+        }
+        map_font_name(
+            &mut index.base.postscript_map,
+            &font.postscript_name.to_lowercase(),
+            font_id,
+            replaced,
+        );
+        for name in &font.full_names {
+            map_font_name(&mut index.base.full_name_map, name, font_id, replaced);
+        }
 
         font.writing_systems
             .iter()
@@ -371,9 +953,20 @@ impl ScannerSink for Inner {
                 .or_insert(vec![family_id]);
         }
     }
+
+    fn skip_file(&mut self, path: PathBuf, error: io::Error) {
+        self.report.sources.push(SourceReport {
+            path: Some(path),
+            outcomes: vec![ScanOutcome::Unreadable(error.to_string())],
+        });
+    }
+
+    fn skip_font(&mut self, outcome: ScanOutcome) {
+        self.report.add(outcome);
+    }
 }
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct FontInfo {
     pub offset: u32,
     pub index: u32,
@@ -382,8 +975,40 @@ pub struct FontInfo {
     pub stretch: Stretch,
     pub weight: Weight,
     pub style: Style,
-    all_names: Vec<String>,
-    name_count: usize,
+    /// Font revision as a 16.16 fixed point number.
+    pub revision: u32,
+    /// Variation axes of a variable font.
+    pub axes: Vec<AxisRange>,
+    /// Subfamily name of the font or of its named instance.
+    pub subfamily: String,
+    /// Index of the named instance in a variable font.
+    pub instance: Option<u16>,
+    /// Normalized coordinates of the named instance.
+    pub coords: Vec<NormalizedCoord>,
+    pub spacing: Spacing,
+    /// True if the font has glyph outlines.
+    pub outline: bool,
+    /// True if the font can be drawn at any size, which includes fonts that
+    /// only have color bitmaps.
+    pub scalable: bool,
+    /// Vendor identifier or manufacturer name. This is empty when unknown.
+    pub foundry: String,
+    /// Characters mapped by the `cmap` table, shared by the named instances
+    /// of a variable font.
+    pub coverage: Arc<Coverage>,
+    /// Color glyph formats from the font tables.
+    pub color: ColorFormats,
+    /// Lowercase family names of the font other than the family it is
+    /// grouped under, such as the legacy family of a static font.
+    pub aliases: Vec<String>,
+    pub postscript_name: String,
+    /// Lowercase full names of the font. Localized names are included when
+    /// all names are requested.
+    pub full_names: Vec<String>,
+    pub(crate) all_names: Vec<String>,
+    pub(crate) name_count: usize,
+    /// Script, language and feature tags from the layout tables. This is
+    /// empty for fonts indexed from fontconfig caches.
     pub writing_systems: Vec<(Tag, Tag, Vec<Tag>)>,
     #[cfg(feature = "emacs")]
     pub supported_charsets: Vec<SmallString>,
@@ -399,13 +1024,104 @@ impl FontInfo {
 
 pub trait ScannerSink {
     fn enter_file(&mut self, path: PathBuf, timestamp: SystemTime, size: u64);
+    fn enter_data(&mut self, data: SharedData);
     fn add_font(&mut self, font: &FontInfo);
+    /// Called for a file that could not be read.
+    fn skip_file(&mut self, path: PathBuf, error: io::Error);
+    /// Called for a font in the current source that was not added.
+    fn skip_font(&mut self, outcome: ScanOutcome);
+}
+
+/// Options for walking directory trees.
+#[derive(Copy, Clone)]
+struct Traversal {
+    max_depth: u32,
+    follow_symlinks: bool,
+    skip_hidden: bool,
+}
+
+impl Default for Traversal {
+    fn default() -> Self {
+        Self {
+            max_depth: 4,
+            follow_symlinks: true,
+            skip_hidden: false,
+        }
+    }
+}
+
+/// Identifies a file independently of the path used to reach it.
+#[cfg(unix)]
+type FileId = (u64, u64);
+#[cfg(not(unix))]
+type FileId = PathBuf;
+
+#[cfg(unix)]
+fn file_id(_path: &Path, metadata: &fs::Metadata) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(path: &Path, _metadata: &fs::Metadata) -> Option<FileId> {
+    fs::canonicalize(path).ok()
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with('.'))
+}
+
+/// Directory that was walked during a scan.
+struct ScannedDir {
+    path: PathBuf,
+    id: FileId,
+    /// Font files and subdirectories in the order they were found. This is
+    /// `None` for directories that were read from a fontconfig cache.
+    entries: Option<Vec<ScannedEntry>>,
+}
+
+enum ScannedEntry {
+    File(PathBuf, Option<FileId>),
+    Dir(PathBuf),
+}
+
+/// Fonts found in a file that was parsed on a worker thread.
+struct FileScan {
+    timestamp: SystemTime,
+    size: u64,
+    fonts: Vec<FontInfo>,
+    issues: Vec<ScanOutcome>,
 }
 
 #[derive(Default)]
 pub struct Scanner {
     font: FontInfo,
-    name: String,
+    issues: Vec<ScanOutcome>,
+    cache: Option<IndexCache>,
+    /// Directories walked since the scanner was created.
+    dirs: Vec<ScannedDir>,
+    /// Directories from a previous scan that are known to be unchanged.
+    reuse: FxHashMap<PathBuf, ScannedDir>,
+    /// Files in reused directories. These are taken from the cache without
+    /// checking whether they were modified.
+    unchanged: FxHashSet<PathBuf>,
+    threads: usize,
+    extensions: Option<Vec<String>>,
+    sniff: bool,
+    traversal: Traversal,
+    visited: FxHashSet<FileId>,
+    include: Vec<Glob>,
+    exclude: Vec<Glob>,
+    #[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
+    fc_cache: bool,
+    #[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
+    fc_caches: Option<FcCaches>,
+    /// Fonts of files that were read from fontconfig caches while walking
+    /// directories.
+    #[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
+    prescanned: FxHashMap<PathBuf, FileScan>,
 }
 
 impl Scanner {
@@ -415,7 +1131,16 @@ impl Scanner {
         all_names: bool,
         sink: &mut impl ScannerSink,
     ) -> Option<()> {
-        self.scan_dir_impl(path, all_names, sink, 0)
+        let mut files = Vec::new();
+        self.scan_dir_impl(path, &mut files, 0)?;
+        if self.threads > 1 && files.len() > 1 {
+            self.scan_files_parallel(&files, all_names, sink);
+        } else {
+            for path in &files {
+                self.scan_file(path, all_names, sink);
+            }
+        }
+        Some(())
     }
 
     pub fn scan_file(
@@ -424,15 +1149,107 @@ impl Scanner {
         all_names: bool,
         sink: &mut impl ScannerSink,
     ) -> Option<()> {
-        let file = fs::File::open(path.as_ref()).ok()?;
-        let metadata = file.metadata().ok()?;
-        let timestamp = metadata.modified().ok()?;
-        let size = metadata.len();
-        let data = unsafe { memmap2::Mmap::map(&file).ok()? };
+        if let Some(scan) = self.take_prescanned(path.as_ref(), all_names) {
+            sink.enter_file(path.as_ref().into(), scan.timestamp, scan.size);
+            for font in &scan.fonts {
+                sink.add_font(font);
+            }
+            return Some(());
+        }
+        if let Some(entry) = self.take_unchanged(path.as_ref(), all_names) {
+            self.add_cached(path.as_ref(), entry, sink);
+            return Some(());
+        }
+        let (file, timestamp, size) = match open_file(path.as_ref()) {
+            Ok(file) => file,
+            Err(err) => {
+                sink.skip_file(path.as_ref().into(), err);
+                return None;
+            }
+        };
+        if let Some(entry) = self
+            .cache
+            .as_mut()
+            .and_then(|cache| cache.take(path.as_ref(), timestamp, size, all_names))
+        {
+            self.add_cached(path.as_ref(), entry, sink);
+            return Some(());
+        }
+        let data = match unsafe { memmap2::Mmap::map(&file) } {
+            Ok(data) => data,
+            Err(err) => {
+                sink.skip_file(path.as_ref().into(), err);
+                return None;
+            }
+        };
+        let data = sfnt_data(&data);
         sink.enter_file(path.as_ref().into(), timestamp, size);
-        self.scan_data(&*data, all_names, |f| sink.add_font(f))
+        let mut fonts = Vec::new();
+        let caching = self.cache.is_some();
+        let result = self.scan_data(&data, all_names, |f| {
+            sink.add_font(f);
+            if caching {
+                fonts.push(f.clone());
+            }
+        });
+        for issue in &self.issues {
+            sink.skip_font(issue.clone());
+        }
+        if let Some(cache) = self.cache.as_mut() {
+            cache.insert(
+                path.as_ref().into(),
+                CacheEntry {
+                    timestamp,
+                    size,
+                    all_names,
+                    fonts,
+                    issues: core::mem::take(&mut self.issues),
+                },
+            );
+        }
+        result
+    }
+
+    /// Adds the fonts of a cache entry to the sink and keeps the entry for
+    /// the next scan.
+    fn add_cached(&mut self, path: &Path, entry: CacheEntry, sink: &mut impl ScannerSink) {
+        sink.enter_file(path.into(), entry.timestamp, entry.size);
+        for font in &entry.fonts {
+            sink.add_font(font);
+        }
+        for issue in &entry.issues {
+            sink.skip_font(issue.clone());
+        }
+        if let Some(cache) = self.cache.as_mut() {
+            cache.insert(path.into(), entry);
+        }
+    }
+
+    /// Removes and returns the cache entry for a file in a reused
+    /// directory.
+    fn take_unchanged(&mut self, path: &Path, all_names: bool) -> Option<CacheEntry> {
+        if !self.unchanged.remove(path) {
+            return None;
+        }
+        self.cache.as_mut()?.take_unchanged(path, all_names)
+    }
+
+    pub fn scan_shared_data(
+        &mut self,
+        data: SharedData,
+        all_names: bool,
+        sink: &mut impl ScannerSink,
+    ) -> Option<()> {
+        sink.enter_data(data.clone());
+        let result = self.scan_data(&data, all_names, |f| sink.add_font(f));
+        for issue in self.issues.drain(..) {
+            sink.skip_font(issue);
+        }
+        result
     }
 
+    /// Scans all fonts in the specified data. Fonts that could not be
+    /// scanned are recorded in `issues`.
     pub fn scan_data(
         &mut self,
         data: &[u8],
@@ -440,57 +1257,363 @@ impl Scanner {
         mut f: impl FnMut(&FontInfo),
     ) -> Option<()> {
         self.font.name.clear();
-        let font_data = FontDataRef::new(data)?;
+        self.issues.clear();
+        let font_data = match FontDataRef::new(data) {
+            Some(font_data) => font_data,
+            None => {
+                self.issues.push(ScanOutcome::UnsupportedFormat);
+                return None;
+            }
+        };
         for i in 0..font_data.len() {
-            if let Some(font) = font_data.get(i) {
-                self.scan_font(font, i as u32, all_names, &mut f);
+            let index = i as u32;
+            match font_data.get(i) {
+                Some(font) => {
+                    if self.scan_font(font, index, all_names, &mut f).is_none() {
+                        self.issues.push(ScanOutcome::MissingFamilyName { index });
+                    }
+                }
+                None => self.issues.push(ScanOutcome::InvalidFont { index }),
             }
         }
         Some(())
     }
 
-    fn scan_dir_impl(
+    /// Parses the specified files on multiple threads and adds the results
+    /// to the sink in the order of the files so that identifiers do not
+    /// depend on thread timing.
+    fn scan_files_parallel(
         &mut self,
-        path: impl AsRef<Path>,
+        files: &[PathBuf],
         all_names: bool,
         sink: &mut impl ScannerSink,
-        recurse: u32,
-    ) -> Option<()> {
-        if recurse > 4 {
-            return Some(());
+    ) {
+        let mut results = Vec::with_capacity(files.len());
+        let mut pending = Vec::new();
+        let mut prescanned = Vec::with_capacity(files.len());
+        for (i, path) in files.iter().enumerate() {
+            if let Some(scan) = self.take_prescanned(path, all_names) {
+                results.push(Some(Ok(scan)));
+                prescanned.push(true);
+                continue;
+            }
+            prescanned.push(false);
+            let cached = match self.take_unchanged(path, all_names) {
+                Some(entry) => Some(entry),
+                None => self.cache.as_mut().and_then(|cache| {
+                    let metadata = fs::metadata(path).ok()?;
+                    let timestamp = metadata.modified().ok()?;
+                    cache.take(path, timestamp, metadata.len(), all_names)
+                }),
+            }
+            .map(|entry| {
+                Ok(FileScan {
+                    timestamp: entry.timestamp,
+                    size: entry.size,
+                    fonts: entry.fonts,
+                    issues: entry.issues,
+                })
+            });
+            if cached.is_none() {
+                pending.push(i);
+            }
+            results.push(cached);
         }
-        let mut lower_ext = [0u8; 3];
-        for entry in fs::read_dir(path).ok()? {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-                if path.is_file() {
-                    let mut is_dfont = false;
-                    match path.extension().map(|e| e.to_str()).flatten() {
-                        Some("dfont") => is_dfont = true,
-                        Some(ext) => {
-                            let ext = ext.as_bytes();
-                            if ext.len() != 3 {
-                                continue;
-                            }
-                            for i in 0..3 {
-                                lower_ext[i] = ext[i].to_ascii_lowercase();
-                            }
+        let next = AtomicUsize::new(0);
+        let threads = self.threads.min(pending.len());
+        let batches: Vec<Vec<(usize, io::Result<FileScan>)>> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut scanner = Scanner::default();
+                        let mut batch = Vec::new();
+                        while let Some(&i) = pending.get(next.fetch_add(1, Ordering::Relaxed)) {
+                            batch.push((i, scanner.read_file(&files[i], all_names)));
                         }
-                        None => continue,
-                    };
-                    if !is_dfont {
-                        match &lower_ext {
-                            b"ttf" | b"otf" | b"ttc" | b"otc" => {}
-                            _ => continue,
-                        }
-                    }
-                    self.scan_file(&path, all_names, sink);
-                } else if path.is_dir() {
-                    self.scan_dir_impl(&path, all_names, sink, recurse + 1);
+                        batch
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap())
+                .collect()
+        });
+        for (i, scan) in batches.into_iter().flatten() {
+            results[i] = Some(scan);
+        }
+        for ((path, scan), prescanned) in files.iter().zip(results).zip(prescanned) {
+            let scan = match scan {
+                Some(Ok(scan)) => scan,
+                Some(Err(err)) => {
+                    sink.skip_file(path.clone(), err);
+                    continue;
                 }
+                None => continue,
+            };
+            sink.enter_file(path.clone(), scan.timestamp, scan.size);
+            for font in &scan.fonts {
+                sink.add_font(font);
+            }
+            for issue in &scan.issues {
+                sink.skip_font(issue.clone());
+            }
+            if prescanned {
+                continue;
+            }
+            if let Some(cache) = self.cache.as_mut() {
+                cache.insert(
+                    path.clone(),
+                    CacheEntry {
+                        timestamp: scan.timestamp,
+                        size: scan.size,
+                        all_names,
+                        fonts: scan.fonts,
+                        issues: scan.issues,
+                    },
+                );
             }
         }
-        Some(())
+    }
+
+    /// Parses the specified file and returns all fonts it contains.
+    fn read_file(&mut self, path: &Path, all_names: bool) -> io::Result<FileScan> {
+        let (file, timestamp, size) = open_file(path)?;
+        let data = unsafe { memmap2::Mmap::map(&file)? };
+        let data = sfnt_data(&data);
+        let mut fonts = Vec::new();
+        self.scan_data(&data, all_names, |f| fonts.push(f.clone()));
+        Ok(FileScan {
+            timestamp,
+            size,
+            fonts,
+            issues: core::mem::take(&mut self.issues),
+        })
+    }
+
+    /// Collects the font files in the specified directory and its
+    /// subdirectories.
+    fn scan_dir_impl(
+        &mut self,
+        path: impl AsRef<Path>,
+        files: &mut Vec<PathBuf>,
+        recurse: u32,
+    ) -> Option<()> {
+        if recurse > self.traversal.max_depth || self.is_excluded(path.as_ref(), true) {
+            return Some(());
+        }
+        if let Some(dir) = self.reuse.remove(path.as_ref()) {
+            self.reuse_dir(dir, files, recurse);
+            return Some(());
+        }
+        // Skip directories that were already scanned through another path
+        // or that link back to one of their ancestors.
+        let metadata = fs::metadata(&path).ok()?;
+        let id = file_id(path.as_ref(), &metadata)?;
+        if !self.visit(&id) {
+            return Some(());
+        }
+        #[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
+        if let Some(subdirs) = self.read_fc_cache(path.as_ref(), files) {
+            self.dirs.push(ScannedDir {
+                path: path.as_ref().into(),
+                id,
+                entries: None,
+            });
+            for subdir in subdirs {
+                let is_link = fs::symlink_metadata(&subdir).is_ok_and(|m| m.is_symlink());
+                if (is_link && !self.traversal.follow_symlinks)
+                    || (self.traversal.skip_hidden && is_hidden(&subdir))
+                {
+                    continue;
+                }
+                self.scan_dir_impl(&subdir, files, recurse + 1);
+            }
+            return Some(());
+        }
+        let entries = fs::read_dir(&path).ok()?;
+        let mut scanned = Vec::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_link = entry.file_type().is_ok_and(|ty| ty.is_symlink());
+            if is_link && !self.traversal.follow_symlinks {
+                continue;
+            }
+            let metadata = match fs::metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            if metadata.is_file() {
+                if self.is_font_file(&path) && self.is_included(&path) {
+                    let id = file_id(&path, &metadata);
+                    match &id {
+                        Some(id) if !self.visit(id) => {}
+                        _ => files.push(path.clone()),
+                    }
+                    scanned.push(ScannedEntry::File(path, id));
+                }
+            } else if metadata.is_dir() {
+                if self.traversal.skip_hidden && is_hidden(&path) {
+                    continue;
+                }
+                scanned.push(ScannedEntry::Dir(path.clone()));
+                self.scan_dir_impl(&path, files, recurse + 1);
+            }
+        }
+        self.dirs.push(ScannedDir {
+            path: path.as_ref().into(),
+            id,
+            entries: Some(scanned),
+        });
+        Some(())
+    }
+
+    /// Collects the font files of a directory from a previous scan without
+    /// reading it again. Subdirectories are reused when they are unchanged
+    /// as well.
+    fn reuse_dir(&mut self, dir: ScannedDir, files: &mut Vec<PathBuf>, recurse: u32) {
+        if !self.visit(&dir.id) {
+            return;
+        }
+        for entry in dir.entries.iter().flatten() {
+            match entry {
+                ScannedEntry::File(path, id) => {
+                    if id.as_ref().is_some_and(|id| !self.visit(id)) {
+                        continue;
+                    }
+                    self.unchanged.insert(path.clone());
+                    files.push(path.clone());
+                }
+                ScannedEntry::Dir(path) => {
+                    self.scan_dir_impl(path, files, recurse + 1);
+                }
+            }
+        }
+        self.dirs.push(dir);
+    }
+
+    /// Marks a file or directory as visited. Returns false if it was
+    /// visited before.
+    #[allow(clippy::clone_on_copy)] // `FileId` is only `Copy` on unix.
+    fn visit(&mut self, id: &FileId) -> bool {
+        self.visited.insert(id.clone())
+    }
+
+    /// Returns true if the path matches an exclude pattern. Directories
+    /// also match patterns that end with a separator, such as `dir/**`.
+    fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        self.exclude
+            .iter()
+            .any(|glob| glob.matches(path) || (is_dir && glob.matches(&path.join(""))))
+    }
+
+    /// Returns true if the file matches an include pattern, or if none were
+    /// specified, and is not excluded.
+    fn is_included(&self, path: &Path) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|glob| glob.matches(path)))
+            && !self.is_excluded(path, false)
+    }
+
+    /// Returns true if the file has an accepted extension or, when content
+    /// sniffing is enabled, begins with a font signature.
+    fn is_font_file(&self, path: &Path) -> bool {
+        let accepted = match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) => match &self.extensions {
+                Some(extensions) => extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)),
+                None => is_default_extension(ext),
+            },
+            None => false,
+        };
+        accepted || (self.sniff && has_font_signature(path))
+    }
+
+    /// Removes and returns the fonts of a file that were read from a
+    /// fontconfig cache.
+    fn take_prescanned(&mut self, path: &Path, all_names: bool) -> Option<FileScan> {
+        #[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
+        {
+            let mut scan = self.prescanned.remove(path)?;
+            if !all_names {
+                for font in &mut scan.fonts {
+                    font.name_count = 0;
+                    font.full_names.truncate(1);
+                }
+            }
+            Some(scan)
+        }
+        #[cfg(not(all(unix, not(any(target_os = "macos", target_os = "android")))))]
+        {
+            let _ = (path, all_names);
+            None
+        }
+    }
+
+    /// Collects the font files listed in a current fontconfig cache for the
+    /// directory and returns its subdirectories. Returns `None` if the
+    /// directory has no current cache.
+    ///
+    /// Files whose fonts cannot be located without reading them, such as
+    /// collections that fail to parse, are collected to be scanned as
+    /// usual.
+    #[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
+    fn read_fc_cache(&mut self, dir: &Path, files: &mut Vec<PathBuf>) -> Option<Vec<PathBuf>> {
+        let cached = self.fc_caches.as_ref()?.get(dir)?;
+        let subdirs = cached.subdirs.clone();
+        let mut listed: Vec<(PathBuf, Vec<&CachedFont>)> = Vec::new();
+        for font in &cached.fonts {
+            // Named instances of variable fonts are not indexed separately.
+            if font.index >> 16 != 0 {
+                continue;
+            }
+            match listed.iter_mut().find(|(file, _)| *file == font.file) {
+                Some((_, fonts)) => fonts.push(font),
+                None => listed.push((font.file.clone(), vec![font])),
+            }
+        }
+        let listed: Vec<_> = listed
+            .into_iter()
+            .map(|(file, fonts)| {
+                // Axis ranges of variable fonts and the formats of color
+                // fonts are not in the cache, so these files are parsed.
+                if fonts.iter().any(|font| font.variable || font.color) {
+                    return (file, None);
+                }
+                let offsets = font_offsets(&file, fonts.iter().map(|font| font.index));
+                let fonts = offsets.map(|offsets| {
+                    fonts
+                        .iter()
+                        .zip(offsets)
+                        .filter_map(|(font, offset)| cached_font_info(font, offset))
+                        .collect::<Vec<_>>()
+                });
+                (file, fonts)
+            })
+            .collect();
+        for (path, fonts) in listed {
+            let metadata = match fs::metadata(&path) {
+                Ok(metadata) if metadata.is_file() => metadata,
+                _ => continue,
+            };
+            if !self.is_font_file(&path) || !self.is_included(&path) {
+                continue;
+            }
+            if let Some(id) = file_id(&path, &metadata) {
+                if !self.visited.insert(id) {
+                    continue;
+                }
+            }
+            if let (Some(fonts), Ok(timestamp)) = (fonts, metadata.modified()) {
+                let scan = FileScan {
+                    timestamp,
+                    size: metadata.len(),
+                    fonts,
+                    issues: Vec::new(),
+                };
+                self.prescanned.insert(path.clone(), scan);
+            }
+            files.push(path);
+        }
+        Some(subdirs)
     }
 
     fn scan_font(
@@ -502,41 +1625,37 @@ impl Scanner {
     ) -> Option<()> {
         self.font.name_count = 0;
         let strings = font.localized_strings();
-        let vars = font.variations();
-        let var_count = vars.len();
         self.font.name.clear();
-        // Use typographic family for variable fonts that tend to encode the
-        // full style in the standard family name.
-        let mut nid = if var_count != 0 {
-            StringId::TypographicFamily
-        } else {
-            StringId::Family
+        // Group faces under their WWS or typographic family so that all
+        // weights and widths of a design share a family. The remaining family
+        // names, such as the legacy family of static fonts that includes the
+        // weight, are kept as aliases.
+        let find_name = |id| {
+            strings
+                .find_by_id(id, Some("en"))
+                .or_else(|| strings.find_by_id(id, None))
         };
-        if let Some(name) = strings.find_by_id(nid, Some("en")) {
-            self.font.name.extend(name.chars());
-        } else if let Some(name) = strings.find_by_id(nid, None) {
-            self.font.name.extend(name.chars());
-        }
-        if self.font.name.is_empty() {
-            nid = if nid == StringId::Family {
-                StringId::TypographicFamily
-            } else {
-                StringId::Family
+        let mut nid = StringId::Family;
+        let mut subfamily = StringId::SubFamily;
+        self.font.aliases.clear();
+        for (id, sub) in [
+            (StringId::WwsFamily, StringId::WwsSubFamily),
+            (StringId::TypographicFamily, StringId::TypographicSubFamily),
+            (StringId::Family, StringId::SubFamily),
+        ] {
+            let name = match find_name(id) {
+                Some(name) => name,
+                None => continue,
             };
-            if let Some(name) = strings.find_by_id(nid, Some("en")) {
-                self.name.extend(name.chars());
-            } else if let Some(name) = strings.find_by_id(nid, None) {
-                self.name.extend(name.chars());
-            }
-        }
-        if !self.name.is_empty() && self.name.len() < self.font.name.len() {
-            core::mem::swap(&mut self.name, &mut self.font.name);
-        }
-        if self.font.name.is_empty() {
-            if let Some(name) = strings.find_by_id(nid, Some("en")) {
-                self.font.name.extend(name.chars());
-            } else if let Some(name) = strings.find_by_id(nid, None) {
+            if self.font.name.is_empty() {
                 self.font.name.extend(name.chars());
+                nid = id;
+                subfamily = sub;
+            } else {
+                let alias: String = name.chars().flat_map(char::to_lowercase).collect();
+                if alias != self.font.name.to_lowercase() && !self.font.aliases.contains(&alias) {
+                    self.font.aliases.push(alias);
+                }
             }
         }
         if self.font.name.is_empty() {
@@ -549,6 +1668,48 @@ impl Scanner {
         self.font.style = style;
         self.font.index = index;
         self.font.offset = font.offset;
+        self.font.revision = font_revision(&font);
+        self.font.subfamily.clear();
+        if let Some(name) = find_name(subfamily) {
+            self.font.subfamily.extend(name.chars());
+        }
+        self.font.instance = None;
+        self.font.coords.clear();
+        self.font.outline = [b"glyf", b"CFF ", b"CFF2"]
+            .iter()
+            .any(|tag| font.table_by_tag(swash::tag_from_bytes(tag)).is_some());
+        self.font.spacing = font_spacing(&font, self.font.outline);
+        let mut chars = Vec::new();
+        font.charmap().enumerate(|ch, glyph| {
+            if glyph != 0 {
+                chars.push(ch);
+            }
+        });
+        self.font.coverage = Arc::new(Coverage::from_chars(chars));
+        self.font.color = color_formats(&font);
+        self.font.scalable = self.font.outline
+            || self
+                .font
+                .color
+                .intersects(ColorFormats::CBDT | ColorFormats::SBIX);
+        self.font.foundry.clear();
+        match font_vendor(&font) {
+            Some(vendor) => self.font.foundry.push_str(vendor),
+            None => {
+                if let Some(name) = find_name(StringId::Manufacturer) {
+                    self.font.foundry.extend(name.chars());
+                }
+            }
+        }
+        self.font.axes = font
+            .variations()
+            .map(|var| AxisRange {
+                tag: var.tag(),
+                min: var.min_value(),
+                default: var.default_value(),
+                max: var.max_value(),
+            })
+            .collect();
         let mut count = 0;
         if all_names {
             for name in strings
@@ -567,6 +1728,27 @@ impl Scanner {
             }
         }
         self.font.name_count = count;
+        self.font.postscript_name.clear();
+        if let Some(name) = strings.find_by_id(StringId::PostScript, None) {
+            self.font.postscript_name.extend(name.chars());
+        }
+        self.font.full_names.clear();
+        if let Some(name) = strings
+            .find_by_id(StringId::Full, Some("en"))
+            .or_else(|| strings.find_by_id(StringId::Full, None))
+        {
+            self.font
+                .full_names
+                .push(name.chars().flat_map(char::to_lowercase).collect());
+        }
+        if all_names {
+            for name in strings.filter(|name| name.id() == StringId::Full && name.is_unicode()) {
+                let name: String = name.chars().flat_map(char::to_lowercase).collect();
+                if !self.font.full_names.contains(&name) {
+                    self.font.full_names.push(name);
+                }
+            }
+        }
         self.font.writing_systems = font
             .writing_systems()
             .map(|w| {
@@ -580,59 +1762,506 @@ impl Scanner {
 
         #[cfg(feature = "emacs")]
         {
-            let supported_charsets: Vec<SmallString> = EMACS_CHARSET_MAP
-                .iter()
-                .filter_map(|(charset, (chars, language))| {
-                    if let Some(language) = language
-                        .as_ref()
-                        .map(|language| Language::parse(language.as_str()))
-                        .flatten()
-                        .map(|lang| lang.to_opentype())
-                        .flatten()
-                    {
-                        if self
-                            .font
-                            .writing_systems
-                            .iter()
-                            .find(|(_, language_tag, _)| *language_tag == language)
-                            .is_none()
-                        {
-                            return None;
-                        }
-                    }
-                    if chars
-                        .iter()
-                        .all(|codepoint| font.charmap().map(*codepoint) != 0)
-                    {
-                        return Some(SmallString::new(charset.as_str()));
-                    }
+            let charmap = font.charmap();
+            let (charsets, scripts) =
+                emacs_coverage(&self.font.writing_systems, |ch| charmap.map(ch) != 0);
+            self.font.supported_charsets = charsets;
+            self.font.supported_scripts = scripts;
+        }
 
-                    None
-                })
-                .collect();
+        f(&self.font);
 
-            self.font.supported_charsets = supported_charsets;
+        // Named instances are listed as fonts of their own with the names
+        // and attributes of the instance. Instances with the attributes of
+        // the default instance are already covered by the variable font.
+        let axes = core::mem::take(&mut self.font.axes);
+        let base_attrs = self.font.attrs;
+        let family = self.font.name.to_lowercase();
+        for instance in font.instances() {
+            let attrs = instance_attributes(&axes, instance.values(), base_attrs);
+            if attrs == base_attrs {
+                continue;
+            }
+            let subfamily = match instance.name(Some("en")).or_else(|| instance.name(None)) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            self.font.attrs = attrs;
+            let (stretch, weight, style) = self.font.attrs.parts();
+            self.font.stretch = stretch;
+            self.font.weight = weight;
+            self.font.style = style;
+            self.font.instance = Some(instance.index() as u16);
+            self.font.coords.clear();
+            self.font.coords.extend(instance.normalized_coords());
+            self.font.postscript_name.clear();
+            if let Some(name) = instance_postscript_name_id(&font, instance.index())
+                .and_then(|id| strings.find_by_id(StringId::Other(id), None))
+            {
+                self.font.postscript_name.extend(name.chars());
+            }
+            self.font.full_names.clear();
+            self.font
+                .full_names
+                .push(format!("{} {}", family, subfamily.to_lowercase()));
+            self.font.subfamily = subfamily;
+            f(&self.font);
+        }
+        Some(())
+    }
+}
 
-            let supported_scripts: Vec<SmallString> = SCRIPT_REPRESENTATIVE_CHARS
-                .iter()
-                .filter_map(|(script, chars)| {
-                    if chars
-                        .iter()
-                        .all(|codepoint| font.charmap().map(*codepoint) != 0)
-                    {
-                        return Some(SmallString::new(script.as_str()));
-                    }
+/// Returns the revision of a font from the `head` table, falling back to
+/// the version string in the name table.
+fn font_revision(font: &FontRef) -> u32 {
+    let head = font.table_by_tag(swash::tag_from_bytes(b"head"));
+    match head.and_then(|head| head.get(4..8)) {
+        Some(revision) if revision != [0; 4] => {
+            u32::from_be_bytes([revision[0], revision[1], revision[2], revision[3]])
+        }
+        _ => font
+            .localized_strings()
+            .find_by_id(StringId::Version, None)
+            .and_then(|version| parse_version(&version.to_string()))
+            .unwrap_or(0),
+    }
+}
 
-                    None
-                })
-                .collect();
+/// Returns the vendor identifier from the `OS/2` table without trailing
+/// blanks. As in fontconfig, it is ignored in version 0 tables.
+fn font_vendor<'a>(font: &'a FontRef) -> Option<&'a str> {
+    let os2 = font.table_by_tag(swash::tag_from_bytes(b"OS/2"))?;
+    let version = u16::from_be_bytes([*os2.first()?, *os2.get(1)?]);
+    if version == 0 || version == 0xFFFF {
+        return None;
+    }
+    let vendor = core::str::from_utf8(os2.get(58..62)?).ok()?;
+    Some(vendor.trim_end_matches([' ', '\0'])).filter(|vendor| !vendor.is_empty())
+}
 
-            self.font.supported_scripts = supported_scripts;
+/// Returns the color glyph formats of a font from its tables.
+fn color_formats(font: &FontRef) -> ColorFormats {
+    let table = |tag| font.table_by_tag(swash::tag_from_bytes(tag));
+    let u16_at = |data: &[u8], offset: usize| {
+        data.get(offset..offset + 2)
+            .map_or(0, |bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    };
+    let mut formats = ColorFormats::default();
+    if let Some(colr) = table(b"COLR") {
+        // Version 1 tables may also have layered glyphs for version 0
+        // renderers.
+        if u16_at(colr, 2) != 0 {
+            formats |= ColorFormats::COLR_V0;
         }
+        if u16_at(colr, 0) >= 1 {
+            formats |= ColorFormats::COLR_V1;
+        }
+    }
+    if table(b"CBDT").is_some() && table(b"CBLC").is_some() {
+        formats |= ColorFormats::CBDT;
+    }
+    if table(b"sbix").is_some() {
+        formats |= ColorFormats::SBIX;
+    }
+    if table(b"SVG ").is_some() {
+        formats |= ColorFormats::SVG;
+    }
+    formats
+}
 
-        f(&self.font);
-        Some(())
+/// Returns the spacing of a font from the advances of its mapped glyphs.
+/// Fonts that are marked as fixed pitch are monospaced when their ASCII
+/// glyphs have the same advance, even if some other glyphs are wider.
+fn font_spacing(font: &FontRef, outline: bool) -> Spacing {
+    // Advances within about 3% are the same, as in fontconfig.
+    let same = |a: f32, b: f32| (a - b).abs() <= a.max(b) / 33.;
+    let metrics = font.glyph_metrics(&[]);
+    let mut advances: Vec<f32> = Vec::new();
+    let mut ascii = None;
+    let mut ascii_fixed = true;
+    font.charmap().enumerate(|ch, glyph| {
+        let advance = metrics.advance_width(glyph);
+        if ch < 0x20 || glyph == 0 || advance <= 0. {
+            return;
+        }
+        if (0x21..0x7F).contains(&ch) {
+            match ascii {
+                Some(first) => ascii_fixed &= same(first, advance),
+                None => ascii = Some(advance),
+            }
+        }
+        if advances.len() < 3 && !advances.iter().any(|a| same(*a, advance)) {
+            advances.push(advance);
+        }
+    });
+    let spacing = match advances[..] {
+        [_] => Spacing::Mono,
+        [a, b] if same(a.max(b), a.min(b) * 2.) => Spacing::Dual,
+        _ if ascii.is_some() && ascii_fixed && is_fixed_pitch(font) => Spacing::Mono,
+        _ => Spacing::Proportional,
+    };
+    // Monospaced fonts without outlines are character cell fonts.
+    if spacing == Spacing::Mono && !outline {
+        Spacing::Charcell
+    } else {
+        spacing
+    }
+}
+
+/// Returns true if the `post` table or the PANOSE classification in the
+/// `OS/2` table marks the font as monospaced.
+fn is_fixed_pitch(font: &FontRef) -> bool {
+    let post = font.table_by_tag(swash::tag_from_bytes(b"post"));
+    if post
+        .and_then(|post| post.get(12..16))
+        .is_some_and(|fixed| fixed != [0; 4])
+    {
+        return true;
+    }
+    // The proportion digit is 9 for monospaced Latin text fonts and the
+    // spacing digit is 3 for monospaced hand written and symbol fonts.
+    let os2 = font.table_by_tag(swash::tag_from_bytes(b"OS/2"));
+    match os2.and_then(|os2| os2.get(32..42)) {
+        Some(panose) => matches!((panose[0], panose[3]), (2, 9) | (3, 3) | (5, 3)),
+        None => false,
+    }
+}
+
+/// Returns the name identifier of the PostScript name of a named instance
+/// from the `fvar` table. This is read directly because swash looks for it
+/// at the start of the table rather than in the instance record.
+fn instance_postscript_name_id(font: &FontRef, index: usize) -> Option<u16> {
+    let fvar = font.table_by_tag(swash::tag_from_bytes(b"fvar"))?;
+    let u16_at = |offset: usize| {
+        fvar.get(offset..offset + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]) as usize)
+    };
+    let axis_count = u16_at(8)?;
+    let instance_size = u16_at(14)?;
+    if instance_size != 4 + axis_count * 4 + 2 {
+        return None;
     }
+    let instances = u16_at(4)? + axis_count * u16_at(10)?;
+    let id = u16_at(instances + index * instance_size + instance_size - 2)?;
+    Some(id as u16)
+}
+
+/// Parses the first number in a version string such as `Version 2.037`
+/// as a 16.16 fixed point number.
+fn parse_version(version: &str) -> Option<u32> {
+    let start = version.find(|c: char| c.is_ascii_digit())?;
+    let number = &version[start..];
+    let end = number
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(number.len());
+    let mut parts = number[..end].splitn(3, '.');
+    let major = parts.next()?.parse::<u32>().ok()?.min(0xFFFF);
+    let minor = match parts.next().filter(|minor| !minor.is_empty()) {
+        Some(minor) => format!("0.{}", minor).parse::<f64>().ok()?,
+        None => 0.0,
+    };
+    Some(major << 16 | ((minor * 65536.0) as u32).min(0xFFFF))
+}
+
+/// Returns the Emacs charsets and scripts supported by a font with the
+/// specified writing systems and character coverage.
+#[cfg(feature = "emacs")]
+fn emacs_coverage(
+    writing_systems: &[(Tag, Tag, Vec<Tag>)],
+    has_char: impl Fn(u32) -> bool,
+) -> (Vec<SmallString>, Vec<SmallString>) {
+    let charsets = EMACS_CHARSET_MAP
+        .iter()
+        .filter_map(|(charset, (chars, language))| {
+            if let Some(language) = language
+                .as_ref()
+                .map(|language| Language::parse(language.as_str()))
+                .flatten()
+                .map(|lang| lang.to_opentype())
+                .flatten()
+            {
+                if writing_systems
+                    .iter()
+                    .find(|(_, language_tag, _)| *language_tag == language)
+                    .is_none()
+                {
+                    return None;
+                }
+            }
+            if chars.iter().all(|codepoint| has_char(*codepoint)) {
+                return Some(SmallString::new(charset.as_str()));
+            }
+
+            None
+        })
+        .collect();
+    let scripts = SCRIPT_REPRESENTATIVE_CHARS
+        .iter()
+        .filter_map(|(script, chars)| {
+            if chars.iter().all(|codepoint| has_char(*codepoint)) {
+                return Some(SmallString::new(script.as_str()));
+            }
+
+            None
+        })
+        .collect();
+    (charsets, scripts)
+}
+
+/// Converts a font pattern from a fontconfig cache to font information.
+/// The family name is the first English name or the first name if there
+/// is none, and variable fonts use their regular or lightest instance.
+#[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
+fn cached_font_info(font: &CachedFont, offset: u32) -> Option<FontInfo> {
+    let (name, _) = font
+        .families
+        .iter()
+        .find(|(_, lang)| lang.as_deref() == Some("en"))
+        .or_else(|| font.families.first())?;
+    let pick = |range: Option<(f64, f64)>, regular: f64| {
+        range.map(|(min, max)| {
+            if (min..=max).contains(&regular) {
+                regular
+            } else {
+                min
+            }
+        })
+    };
+    let weight = Weight(pick(font.weight, 80.).map_or(400, fontconfig::ot_weight));
+    let stretch = pick(font.width, 100.).map_or(Stretch::NORMAL, |width| {
+        Stretch::from_percentage(width as f32)
+    });
+    // fontconfig reports fonts with "Oblique" in their style name as
+    // oblique even when they are marked as italic, as most are.
+    let style = match font.slant {
+        Some(slant) if slant > 0 => Style::Italic,
+        _ => Style::Normal,
+    };
+    let mut all_names: Vec<String> = Vec::new();
+    for (name, _) in &font.families {
+        let name = name.to_lowercase();
+        if !all_names.contains(&name) {
+            all_names.push(name);
+        }
+    }
+    // fontconfig lists the WWS, typographic and legacy families in that
+    // order, so the English names after the first are aliases.
+    let mut aliases: Vec<String> = Vec::new();
+    for (alias, lang) in &font.families {
+        let alias = alias.to_lowercase();
+        if matches!(lang.as_deref(), None | Some("en"))
+            && alias != name.to_lowercase()
+            && !aliases.contains(&alias)
+        {
+            aliases.push(alias);
+        }
+    }
+    let subfamily = font
+        .styles
+        .iter()
+        .find(|(_, lang)| lang.as_deref() == Some("en"))
+        .or_else(|| font.styles.first())
+        .map(|(style, _)| style.clone())
+        .unwrap_or_default();
+    let mut full_names: Vec<String> = Vec::new();
+    let english = font
+        .full_names
+        .iter()
+        .find(|(_, lang)| lang.as_deref() == Some("en"));
+    for (name, _) in english.into_iter().chain(&font.full_names) {
+        let name = name.to_lowercase();
+        if !full_names.contains(&name) {
+            full_names.push(name);
+        }
+    }
+    #[cfg(feature = "emacs")]
+    let (supported_charsets, supported_scripts) = match &font.charset {
+        Some(charset) => emacs_coverage(&[], |ch| charset.contains(ch)),
+        None => Default::default(),
+    };
+    Some(FontInfo {
+        offset,
+        index: font.index,
+        name: name.clone(),
+        attrs: Attributes::new(stretch, weight, style),
+        stretch,
+        weight,
+        style,
+        revision: font.revision,
+        axes: Vec::new(),
+        subfamily,
+        instance: None,
+        coords: Vec::new(),
+        spacing: font.spacing,
+        outline: font.outline,
+        scalable: font.scalable,
+        foundry: font.foundry.clone().unwrap_or_default(),
+        coverage: Arc::new(
+            font.charset
+                .as_ref()
+                .map(|charset| Coverage::from_chars(charset.chars()))
+                .unwrap_or_default(),
+        ),
+        color: ColorFormats::default(),
+        aliases,
+        postscript_name: font.postscript_name.clone().unwrap_or_default(),
+        full_names,
+        name_count: all_names.len(),
+        all_names,
+        // Caches do not record the scripts and features of layout tables.
+        writing_systems: Vec::new(),
+        #[cfg(feature = "emacs")]
+        supported_charsets,
+        #[cfg(feature = "emacs")]
+        supported_scripts,
+    })
+}
+
+/// Returns the offsets of the fonts at the specified indices in a file
+/// listed in a fontconfig cache. Only the header of font collections is
+/// read. Returns `None` if the offsets cannot be determined this way.
+#[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
+fn font_offsets(path: &Path, mut indices: impl Iterator<Item = u32>) -> Option<Vec<u32>> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    match ext.as_str() {
+        "ttf" | "otf" | "woff" | "woff2" => {
+            indices.map(|index| (index == 0).then_some(0)).collect()
+        }
+        "ttc" | "otc" => {
+            let mut file = fs::File::open(path).ok()?;
+            let mut header = [0u8; 12];
+            file.read_exact(&mut header).ok()?;
+            if &header[..4] != b"ttcf" {
+                return None;
+            }
+            let count = u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+            let mut offsets = vec![0u8; count.min(0xFFFF) as usize * 4];
+            file.read_exact(&mut offsets).ok()?;
+            indices.try_fold(Vec::new(), |mut result, index| {
+                let bytes = offsets.get(index as usize * 4..index as usize * 4 + 4)?;
+                result.push(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+                Some(result)
+            })
+        }
+        _ => None,
+    }
+}
+
+fn is_default_extension(ext: &str) -> bool {
+    match ext.to_ascii_lowercase().as_str() {
+        "ttf" | "otf" | "ttc" | "otc" | "dfont" => true,
+        #[cfg(feature = "woff")]
+        "woff" | "woff2" => true,
+        _ => false,
+    }
+}
+
+/// Maps alternate family names, such as legacy family names, to the fonts
+/// that have them. A name that all fonts of a family have maps to the family
+/// while other names map to an alias family with only the fonts that have
+/// the name. Names of other families take precedence.
+fn map_family_aliases(
+    index: &mut StaticIndex,
+    aliases: FxHashMap<SmallString, (FamilyId, Vec<FontId>)>,
+) {
+    let mut aliases: Vec<_> = aliases.into_iter().collect();
+    aliases.sort_unstable_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+    for (name, (family_id, ids)) in aliases {
+        if index.base.family_map.contains_key(name.as_str()) {
+            continue;
+        }
+        let family = &index.families[family_id.to_usize()];
+        let fonts: Vec<_> = family
+            .fonts
+            .iter()
+            .filter(|font| ids.contains(&font.id))
+            .cloned()
+            .collect();
+        if fonts.is_empty() {
+            continue;
+        }
+        let id = if fonts.len() == family.fonts.len() {
+            family_id
+        } else {
+            let id = FamilyId((index.families.len() + index.alias_families.len()) as u32);
+            let alias_family = FamilyData {
+                id,
+                name: family.name.clone(),
+                fonts,
+                shadowed: Vec::new(),
+                layer: family.layer,
+                has_stretch: family.has_stretch,
+            };
+            index.alias_families.push(alias_family);
+            id
+        };
+        index.base.family_map.insert(name, id);
+    }
+}
+
+/// Maps a lowercase font name to a font. Existing entries are only replaced
+/// by a font that replaced a duplicate or a family in a lower layer.
+fn map_font_name(map: &mut FxHashMap<SmallString, FontId>, name: &str, id: FontId, replace: bool) {
+    if !name.is_empty() && (replace || !map.contains_key(name)) {
+        map.insert(SmallString::new(name), id);
+    }
+}
+
+/// Returns true if the path is in the user's home directory.
+fn is_user_path(home: Option<&Path>, path: &Path) -> bool {
+    home.is_some_and(|home| path.starts_with(home))
+}
+
+/// Returns true if the file begins with the signature of a font or font
+/// collection.
+fn has_font_signature(path: &Path) -> bool {
+    let mut tag = [0u8; 4];
+    let read = fs::File::open(path).and_then(|mut file| file.read_exact(&mut tag));
+    if read.is_err() {
+        return false;
+    }
+    match &tag {
+        [0, 1, 0, 0] | b"true" | b"OTTO" | b"ttcf" => true,
+        #[cfg(feature = "woff")]
+        b"wOFF" | b"wOF2" => true,
+        _ => false,
+    }
+}
+
+/// Returns the sfnt data for the contents of a font file, decompressing
+/// WOFF and WOFF2 files.
+fn sfnt_data(data: &[u8]) -> Cow<'_, [u8]> {
+    #[cfg(feature = "woff")]
+    if let Some(data) = super::woff::decode(data) {
+        return Cow::Owned(data);
+    }
+    Cow::Borrowed(data)
+}
+
+/// Opens a font file and returns it along with its modification time and
+/// size.
+fn open_file(path: &Path) -> io::Result<(fs::File, SystemTime, u64)> {
+    let file = fs::File::open(path)?;
+    let metadata = file.metadata()?;
+    let timestamp = metadata.modified()?;
+    let size = metadata.len();
+    Ok((file, timestamp, size))
+}
+
+/// Returns the cache directories from the fontconfig configuration or the
+/// default locations if none are configured.
+#[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
+fn fontconfig_cache_dirs(configured: &[PathBuf]) -> Vec<PathBuf> {
+    if !configured.is_empty() {
+        return configured.to_vec();
+    }
+    let mut dirs = vec![PathBuf::from("/var/cache/fontconfig")];
+    if let Some(cache_home) = std::env::var_os("XDG_CACHE_HOME") {
+        dirs.push(Path::new(&cache_home).join("fontconfig"));
+    } else if let Some(home) = std::env::var_os("HOME") {
+        dirs.push(Path::new(&home).join(".cache/fontconfig"));
+    }
+    dirs
 }
 
 #[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
@@ -667,3 +2296,578 @@ fn load_fontconfig() -> fontconfig_parser::FontConfig {
     }
     fontconfig
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_font::{self, TestFont};
+
+    /// Returns the family, subfamily, attributes and path of each font in
+    /// the order of their identifiers.
+    fn fonts(library: &FontLibrary) -> Vec<(String, String, Attributes, Option<PathBuf>)> {
+        let index = library.index();
+        index
+            .base
+            .fonts
+            .iter()
+            .filter_map(|font| index.font_by_id(font.id))
+            .map(|font| {
+                (
+                    font.family_name().to_string(),
+                    font.subfamily_name().to_string(),
+                    font.attributes(),
+                    font.source().path().map(Into::into),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parallel_scan() {
+        test_font::isolate();
+        let dir = test_font::temp_dir("parallel-scan");
+        for (i, family) in ["Alpha", "Beta", "Gamma"].iter().enumerate() {
+            for (weight, subfamily) in [(400, "Regular"), (700, "Bold")] {
+                let font = TestFont {
+                    subfamily: subfamily.into(),
+                    weight,
+                    ..TestFont::new(family)
+                };
+                let path = dir.join(format!("{}-{}.ttf", i, subfamily));
+                fs::write(path, font.build()).unwrap();
+            }
+        }
+        let single = FontLibrary::builder().threads(1).add_dir(&dir).build();
+        let parallel = FontLibrary::builder().threads(4).add_dir(&dir).build();
+        assert_eq!(fonts(&single).len(), 6);
+        assert_eq!(fonts(&single), fonts(&parallel));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn content_sniffing() {
+        test_font::isolate();
+        let dir = test_font::temp_dir("sniffing");
+        let write = |name: &str, data: Vec<u8>| fs::write(dir.join(name), data).unwrap();
+        write("Plain.ttf", TestFont::new("Plain").build());
+        write("Renamed.dat", TestFont::new("Renamed").build());
+        #[cfg(feature = "woff")]
+        write(
+            "Packed.bin",
+            test_font::woff(&TestFont::new("Packed").sfnt_tables()),
+        );
+        write("Notes.txt", b"Not a font".to_vec());
+        write("Short", b"tr".to_vec());
+        let families = |builder: &mut FontLibraryBuilder| {
+            let mut families: Vec<_> = fonts(&builder.add_dir(&dir).build())
+                .into_iter()
+                .map(|(family, ..)| family)
+                .collect();
+            families.sort_unstable();
+            families
+        };
+        assert_eq!(families(&mut FontLibrary::builder()), ["Plain"]);
+        let sniffed = families(FontLibrary::builder().sniff_content(true));
+        #[cfg(feature = "woff")]
+        assert_eq!(sniffed, ["Packed", "Plain", "Renamed"]);
+        #[cfg(not(feature = "woff"))]
+        assert_eq!(sniffed, ["Plain", "Renamed"]);
+        // Custom extensions replace the defaults and ignore case.
+        let custom = families(FontLibrary::builder().extensions(["DAT"]));
+        assert_eq!(custom, ["Renamed"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn traversal() {
+        use std::os::unix::fs::symlink;
+        test_font::isolate();
+        let dir = test_font::temp_dir("traversal");
+        let root = dir.join("root");
+        let faces = [
+            ("Top", root.clone()),
+            ("Depth1", root.join("a")),
+            ("Depth2", root.join("a/b")),
+            ("Hidden", root.join(".hidden")),
+            ("Outside", dir.join("outside")),
+        ];
+        for (family, path) in &faces {
+            fs::create_dir_all(path).unwrap();
+            let data = TestFont::new(family).build();
+            fs::write(path.join(format!("{}.ttf", family)), data).unwrap();
+        }
+        // A link to a directory outside the root, a second path to a
+        // directory that is scanned anyway and a link back to the root.
+        symlink(dir.join("outside"), root.join("link")).unwrap();
+        symlink(root.join("a"), root.join("alias")).unwrap();
+        symlink(&root, root.join("a/b/loop")).unwrap();
+        let families = |builder: &mut FontLibraryBuilder| {
+            let mut families: Vec<_> = fonts(&builder.add_dir(&root).build())
+                .into_iter()
+                .map(|(family, ..)| family)
+                .collect();
+            families.sort_unstable();
+            families
+        };
+        assert_eq!(
+            families(&mut FontLibrary::builder()),
+            ["Depth1", "Depth2", "Hidden", "Outside", "Top"]
+        );
+        assert_eq!(
+            families(FontLibrary::builder().max_depth(1)),
+            ["Depth1", "Hidden", "Outside", "Top"]
+        );
+        assert_eq!(
+            families(FontLibrary::builder().follow_symlinks(false)),
+            ["Depth1", "Depth2", "Hidden", "Top"]
+        );
+        assert_eq!(
+            families(FontLibrary::builder().skip_hidden(true)),
+            ["Depth1", "Depth2", "Outside", "Top"]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
+    #[test]
+    fn exclude_configured_dirs() {
+        test_font::isolate();
+        let dir = test_font::temp_dir("exclude-configured");
+        let write = |path: PathBuf, family| {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, TestFont::new(family).build()).unwrap();
+        };
+        let (configured, cached) = (dir.join("configured"), dir.join("cached"));
+        write(configured.join("Kept.ttf"), "Kept");
+        write(configured.join("Dropped.ttf"), "Dropped");
+        write(configured.join("skip/Skipped.ttf"), "Skipped");
+        write(cached.join("Listed.ttf"), "Listed");
+        write(cached.join("Excluded.ttf"), "Excluded");
+        write(cached.join("skip/Subdir.ttf"), "Subdir");
+        // Cached family names differ from the fonts to show that the cache
+        // was read instead of the files.
+        let cached_font = |file: &str, family: &str| CachedFont {
+            file: cached.join(file),
+            families: vec![(format!("Cached {}", family), None)],
+            outline: true,
+            scalable: true,
+            ..Default::default()
+        };
+        let mut fc_caches = FcCaches::default();
+        fc_caches.insert(
+            &cached,
+            vec![cached.join("skip")],
+            vec![
+                cached_font("Listed.ttf", "Listed"),
+                cached_font("Excluded.ttf", "Excluded"),
+            ],
+        );
+        let mut fontconfig = fontconfig_parser::FontConfig::default();
+        for path in [&configured, &cached] {
+            fontconfig.dirs.push(fontconfig_parser::DirData {
+                path: path.clone(),
+                salt: String::new(),
+            });
+        }
+        let mut builder = FontLibrary::builder();
+        builder
+            .exclude("**/skip")
+            .exclude("Dropped.ttf")
+            .exclude("Excluded.ttf");
+        builder.fontconfig = Some(fontconfig);
+        builder.scanner.fc_caches = Some(fc_caches);
+        let mut families: Vec<_> = fonts(&builder.build())
+            .into_iter()
+            .map(|(family, ..)| family)
+            .collect();
+        families.sort_unstable();
+        assert_eq!(families, ["Cached Listed", "Kept"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
+    #[test]
+    fn generic_chains() {
+        use crate::FontContext;
+        use swash::text::cluster::{CharCluster, Parser, Token};
+        use swash::text::Script;
+        use swash::{StringId, Synthesis};
+
+        test_font::isolate();
+        let dir = test_font::temp_dir("generic-chains");
+        let path = dir.join("fonts.conf");
+        fs::write(
+            &path,
+            r#"<?xml version="1.0"?>
+            <!DOCTYPE fontconfig SYSTEM "urn:fontconfig:fonts.dtd">
+            <fontconfig>
+                <alias>
+                    <family>sans-serif</family>
+                    <prefer><family>Alpha</family></prefer>
+                    <accept><family>Gamma</family></accept>
+                    <default><family>Epsilon</family></default>
+                </alias>
+                <alias>
+                    <family>sans-serif</family>
+                    <prefer><family>Beta</family><family>Missing</family></prefer>
+                    <accept><family>Delta</family></accept>
+                    <default><family>Zeta</family></default>
+                </alias>
+            </fontconfig>"#,
+        )
+        .unwrap();
+        let mut fontconfig = fontconfig_parser::FontConfig::default();
+        fontconfig.merge_config(&path).unwrap();
+        let cyrillic = ('а'..='я').map(|ch| (ch, 600));
+        let greek = ('α'..='ω').map(|ch| (ch, 600));
+        let families = [
+            ("Alpha", vec![]),
+            ("Beta", cyrillic.clone().collect()),
+            ("Gamma", cyrillic.chain(greek.clone()).collect()),
+            ("Delta", greek.collect()),
+            ("Epsilon", vec![]),
+            ("Zeta", vec![('א', 600)]),
+        ];
+        let mut builder = FontLibrary::builder();
+        for (family, chars) in families {
+            let mut font = TestFont::new(family);
+            font.chars.extend(chars);
+            builder.add_data(font.build());
+        }
+        builder.map_generic_families(true);
+        builder.fontconfig = Some(fontconfig);
+        let library = builder.build();
+        let _ = fs::remove_dir_all(&dir);
+        // Preferred families of later aliases follow those of earlier ones
+        // while their accepted families come first, as if each alias was
+        // applied to the list in turn.
+        let index = library.index();
+        let chain: Vec<_> = index
+            .generic_chain(GenericFamily::SansSerif)
+            .iter()
+            .map(|id| index.family_by_id(*id).unwrap().name().to_string())
+            .collect();
+        assert_eq!(
+            chain,
+            ["Alpha", "Beta", "Delta", "Gamma", "Epsilon", "Zeta"]
+        );
+        let mut context = FontContext::new(library);
+        let group = context.register_group("sans-serif", 0, Attributes::default());
+        context.select_group(group);
+        let mut synth = Synthesis::default();
+        let mut family = |ch| {
+            let mut cluster = CharCluster::new();
+            let token = Token {
+                ch,
+                offset: 0,
+                len: 1,
+                info: ch.into(),
+                data: 0,
+            };
+            Parser::new(Script::Latin, core::iter::once(token)).next(&mut cluster);
+            let font = context.map_cluster(&mut cluster, &mut synth).unwrap();
+            let strings = font.as_ref().localized_strings();
+            let name = strings.find_by_id(StringId::Family, None).unwrap();
+            name.chars().collect::<String>()
+        };
+        // Clusters map to the first family in the chain that supports them.
+        assert_eq!(family('A'), "Alpha");
+        assert_eq!(family('ж'), "Beta");
+        assert_eq!(family('λ'), "Delta");
+        assert_eq!(family('א'), "Zeta");
+    }
+
+    #[test]
+    fn duplicate_policy() {
+        test_font::isolate();
+        let dir = test_font::temp_dir("duplicates");
+        // The same face in a system directory, the home directory and
+        // another system directory with a newer revision.
+        let dirs = [dir.join("a"), dir.join("home/b"), dir.join("c")];
+        for (path, revision) in dirs.iter().zip([0x10000, 0x10000, 0x20000]) {
+            fs::create_dir_all(path).unwrap();
+            let font = TestFont {
+                revision,
+                ..TestFont::new("Twin")
+            };
+            fs::write(path.join("Twin.ttf"), font.build()).unwrap();
+        }
+        let build = |policy| {
+            let mut builder = FontLibrary::builder();
+            builder.duplicate_policy(policy).home_dir(dir.join("home"));
+            for path in &dirs {
+                builder.add_dir(path);
+            }
+            builder.build().index()
+        };
+        let dir_of = |font: FontEntry| {
+            let path = font.source().path().unwrap().to_path_buf();
+            let parent = path.parent().unwrap();
+            dirs.iter().position(|dir| dir == parent).unwrap()
+        };
+        let selected = |policy| {
+            let index = build(policy);
+            let family = index.family_by_name("Twin").unwrap();
+            let fonts: Vec<_> = family.fonts().map(dir_of).collect();
+            let mut shadowed: Vec<_> = family.shadowed_fonts().map(dir_of).collect();
+            shadowed.sort_unstable();
+            let query = dir_of(index.query("Twin", ()).unwrap());
+            (fonts, shadowed, query)
+        };
+        let first = selected(DuplicatePolicy::First);
+        let user = selected(DuplicatePolicy::PreferUser);
+        let newer = selected(DuplicatePolicy::PreferNewer);
+        let all = selected(DuplicatePolicy::KeepAll);
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(first, (vec![0], vec![1, 2], 0));
+        assert_eq!(user, (vec![1], vec![0, 2], 1));
+        assert_eq!(newer, (vec![2], vec![0, 1], 2));
+        assert_eq!(all, (vec![0, 1, 2], vec![], 0));
+    }
+
+    #[test]
+    fn layer_override() {
+        test_font::isolate();
+        let dir = test_font::temp_dir("layers");
+        let system = [
+            dir.join("Layered-Regular.ttf"),
+            dir.join("Layered-Bold.ttf"),
+        ];
+        fs::write(&system[0], TestFont::new("Layered").build()).unwrap();
+        let bold = TestFont {
+            subfamily: "Bold".into(),
+            weight: 700,
+            ..TestFont::new("Layered")
+        };
+        fs::write(&system[1], bold.build()).unwrap();
+        let app = TestFont {
+            postscript_name: Some("Layered-App".into()),
+            ..TestFont::new("Layered")
+        }
+        .build();
+        // The application font replaces the system font with the same
+        // attributes, or the whole system family when configured, whether
+        // it is added before or after them.
+        for (whole_family, app_first) in
+            [(false, false), (false, true), (true, false), (true, true)]
+        {
+            let mut builder = FontLibrary::builder();
+            builder.override_families(whole_family);
+            if app_first {
+                builder.add_data(app.clone());
+            }
+            builder.layer(SourceLayer::System);
+            for path in &system {
+                builder.add_file(path);
+            }
+            if !app_first {
+                builder.layer(SourceLayer::App).add_data(app.clone());
+            }
+            let (library, report) = builder.build_with_report();
+            let index = library.index();
+            let family = index.family_by_name("Layered").unwrap();
+            let mut fonts: Vec<_> = family
+                .fonts()
+                .map(|font| font.source().path().map(Path::to_path_buf))
+                .collect();
+            fonts.sort_unstable();
+            let bold = index
+                .query(
+                    "Layered",
+                    Attributes::new(Stretch::NORMAL, Weight::BOLD, Style::Normal),
+                )
+                .unwrap();
+            let font = index.font_by_postscript_name("Layered-App").unwrap();
+            assert!(font.source().path().is_none());
+            // Names of the shadowed system fonts no longer resolve.
+            assert!(index.font_by_postscript_name("Layered-Regular").is_none());
+            if whole_family {
+                assert_eq!(fonts, [None]);
+                assert_eq!(family.shadowed_fonts().count(), 2);
+                assert!(bold.source().path().is_none());
+                assert!(index.font_by_postscript_name("Layered-Bold").is_none());
+                assert!(index.font_by_full_name("Layered Bold").is_none());
+            } else {
+                // Unrelated system faces stay matchable.
+                assert_eq!(fonts, [None, Some(system[1].clone())]);
+                assert_eq!(family.shadowed_fonts().count(), 1);
+                assert_eq!(bold.source().path(), Some(system[1].as_path()));
+                assert!(index.font_by_postscript_name("Layered-Bold").is_some());
+                assert!(index.font_by_full_name("Layered Bold").is_some());
+            }
+            for (i, path) in system.iter().enumerate() {
+                let outcomes = &report.source(path).unwrap().outcomes;
+                assert_eq!(outcomes.len(), 1);
+                assert_eq!(
+                    matches!(outcomes[0], ScanOutcome::Overridden { index: 0, .. }),
+                    app_first && (whole_family || i == 0)
+                );
+            }
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn legacy_family_names() {
+        test_font::isolate();
+        let faces = [
+            ("Legacy Light", "Regular", "Light", 300),
+            ("Legacy", "Regular", "Regular", 400),
+            ("Legacy", "Bold", "Bold", 700),
+        ];
+        let mut builder = FontLibrary::builder();
+        for (family, subfamily, typographic_subfamily, weight) in faces {
+            let font = TestFont {
+                subfamily: subfamily.into(),
+                typographic_family: Some("Legacy".into()),
+                typographic_subfamily: Some(typographic_subfamily.into()),
+                weight,
+                ..TestFont::new(family)
+            };
+            builder.add_data(font.build());
+        }
+        let index = builder.build().index();
+        let weights = |name| {
+            let family = index.family_by_name(name).unwrap();
+            let weights: Vec<_> = family
+                .fonts()
+                .map(|font| font.attributes().weight().0)
+                .collect();
+            let query = index.query(name, ()).unwrap().attributes().weight().0;
+            (weights, query)
+        };
+        assert_eq!(weights("Legacy"), (vec![300, 400, 700], 400));
+        assert_eq!(weights("Legacy Light"), (vec![300], 300));
+        let light = index.family_by_name("Legacy Light").unwrap();
+        assert_eq!(light.name(), "Legacy");
+        assert!(index.family_by_id(light.id()).is_some());
+    }
+
+    #[test]
+    fn named_instances() {
+        test_font::isolate();
+        let font = TestFont {
+            axes: vec![(*b"wght", 100., 400., 900.)],
+            instances: vec![
+                ("Thin".into(), vec![100.]),
+                ("Regular".into(), vec![400.]),
+                ("Bold".into(), vec![700.]),
+            ],
+            ..TestFont::new("Variable")
+        };
+        let library = FontLibrary::builder().add_data(font.build()).build();
+        let subfamilies: Vec<_> = fonts(&library)
+            .into_iter()
+            .map(|(_, subfamily, attrs, _)| (subfamily, attrs.weight().0))
+            .collect();
+        // The regular instance is the default instance of the font.
+        assert_eq!(
+            subfamilies,
+            [
+                ("Regular".into(), 400),
+                ("Thin".into(), 100),
+                ("Bold".into(), 700)
+            ]
+        );
+        let index = library.index();
+        let entries: Vec<_> = index.base.fonts.iter().collect();
+        let instances: Vec<_> = entries
+            .iter()
+            .filter_map(|font| index.font_by_id(font.id))
+            .map(|font| (font.instance(), font.coords().to_vec(), font.axes().len()))
+            .collect();
+        assert_eq!(
+            instances,
+            [
+                (None, vec![], 1),
+                (Some(0), vec![-16384], 0),
+                (Some(2), vec![9831], 0)
+            ]
+        );
+        assert!(Arc::ptr_eq(&entries[0].coverage, &entries[1].coverage));
+        assert!(Arc::ptr_eq(&entries[0].coverage, &entries[2].coverage));
+        let bold = Attributes::new(Stretch::NORMAL, Weight::BOLD, Style::Normal);
+        let query = index.query("Variable", bold).unwrap();
+        assert_eq!(query.subfamily_name(), "Bold");
+    }
+
+    #[test]
+    fn spacing() {
+        let spacing = |font: TestFont| {
+            let data = font.build();
+            let font_ref = FontRef::from_index(&data, 0).unwrap();
+            font_spacing(&font_ref, font.outlines)
+        };
+        let ascii = |advance| ('!'..='~').map(move |ch| (ch, advance));
+        let mono = TestFont {
+            chars: ascii(600).collect(),
+            ..TestFont::new("Mono")
+        };
+        assert_eq!(spacing(mono.clone()), Spacing::Mono);
+        let bitmap = TestFont {
+            outlines: false,
+            ..mono.clone()
+        };
+        assert_eq!(spacing(bitmap), Spacing::Charcell);
+        // Glyphs for accented letters and symbols are often wider or
+        // narrower than the ASCII cell.
+        let wide = TestFont {
+            fixed_pitch: true,
+            chars: ascii(600).chain([('é', 300), ('→', 1000)]).collect(),
+            ..mono
+        };
+        assert_eq!(spacing(wide.clone()), Spacing::Mono);
+        let wide = TestFont {
+            fixed_pitch: false,
+            ..wide
+        };
+        assert_eq!(spacing(wide), Spacing::Proportional);
+        let dual = TestFont {
+            chars: ascii(500).chain([('一', 1000), ('二', 1010)]).collect(),
+            ..TestFont::new("Dual")
+        };
+        assert_eq!(spacing(dual), Spacing::Dual);
+        assert_eq!(
+            spacing(TestFont::new("Proportional")),
+            Spacing::Proportional
+        );
+    }
+
+    #[test]
+    fn foundry() {
+        test_font::isolate();
+        let faces = [
+            ("Regular", 400, *b"ACME", None),
+            ("Bold", 700, [0; 4], Some("Example Type")),
+            ("Black", 900, *b"    ", None),
+        ];
+        let mut builder = FontLibrary::builder();
+        for (subfamily, weight, vendor, manufacturer) in faces {
+            let font = TestFont {
+                subfamily: subfamily.into(),
+                weight,
+                vendor,
+                manufacturer: manufacturer.map(Into::into),
+                ..TestFont::new("Foundry")
+            };
+            builder.add_data(font.build());
+        }
+        let index = builder.build().index();
+        let family = index.family_by_name("Foundry").unwrap();
+        let foundries: Vec<_> = family.fonts().map(|font| font.foundry()).collect();
+        // Vendor identifiers of spaces or zeros fall back to the
+        // manufacturer name.
+        assert_eq!(foundries, ["ACME", "Example Type", ""]);
+        let data = &index.families[family.id().to_usize()];
+        let matching = |foundry| {
+            let id = data.match_(Attributes::default(), None, Some(foundry), None, None)?;
+            index
+                .font_by_id(id)
+                .map(|font| font.subfamily_name().to_string())
+        };
+        assert_eq!(matching("acme").as_deref(), Some("Regular"));
+        assert_eq!(matching(" EXAMPLE type").as_deref(), Some("Bold"));
+        assert_eq!(matching("ACM"), None);
+    }
+}
@@ -0,0 +1,530 @@
+//! Persistent cache of scanned font files.
+//!
+//! The cache records the fonts found in each scanned file along with the
+//! modification time and size of the file. When a file is scanned again and
+//! both values are unchanged, the recorded fonts are replayed into the index
+//! rather than parsing the file. Since every family, font, source and
+//! script/language entry of the index is derived from these records, this
+//! reproduces the same index as a full scan.
+
+use super::builder::FontInfo;
+use super::index_data::Coverage;
+use super::report::ScanOutcome;
+use super::types::{AxisRange, ColorFormats, Spacing};
+use crate::util::fxhash::FxHashMap;
+#[cfg(feature = "emacs")]
+use crate::util::string::SmallString;
+use std::convert::TryInto;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use swash::Attributes;
+
+const MAGIC: [u8; 4] = *b"FIDX";
+const VERSION: u32 = 12;
+
+/// Cached scan results for a single file.
+pub struct CacheEntry {
+    pub timestamp: SystemTime,
+    pub size: u64,
+    pub all_names: bool,
+    pub fonts: Vec<FontInfo>,
+    /// Fonts in the file that could not be scanned.
+    pub issues: Vec<ScanOutcome>,
+}
+
+/// Scan results loaded from and written to a cache file.
+#[derive(Default)]
+pub struct IndexCache {
+    /// Entries loaded from the cache file that have not been used yet.
+    old: FxHashMap<PathBuf, CacheEntry>,
+    /// Entries for files seen since the cache was loaded.
+    new: FxHashMap<PathBuf, CacheEntry>,
+}
+
+impl IndexCache {
+    /// Loads a cache from the specified path. Returns an empty cache if the
+    /// file is missing, corrupt or was written by a different version.
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        let mut cache = Self::default();
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(_) => return cache,
+        };
+        match read_entries(&mut Reader::new(&data)) {
+            Some(entries) => cache.old = entries,
+            None => log::debug!("ignoring invalid font index cache {:?}", path),
+        }
+        cache
+    }
+
+    /// Removes and returns the entry for the specified file if it is still
+    /// current.
+    pub fn take(
+        &mut self,
+        path: &Path,
+        timestamp: SystemTime,
+        size: u64,
+        all_names: bool,
+    ) -> Option<CacheEntry> {
+        let entry = self.take_unchanged(path, all_names)?;
+        if entry.timestamp == timestamp && entry.size == size {
+            Some(entry)
+        } else {
+            None
+        }
+    }
+
+    /// Removes and returns the entry for the specified file without
+    /// checking whether the file was modified since it was scanned.
+    pub fn take_unchanged(&mut self, path: &Path, all_names: bool) -> Option<CacheEntry> {
+        let entry = match self.new.remove(path) {
+            Some(entry) => entry,
+            None => self.old.remove(path)?,
+        };
+        if entry.all_names == all_names {
+            Some(entry)
+        } else {
+            None
+        }
+    }
+
+    /// Marks all entries as unused. Entries for files that are not seen
+    /// again are dropped.
+    pub fn reset(&mut self) {
+        self.old = core::mem::take(&mut self.new);
+    }
+
+    /// Records the scan results for the specified file.
+    pub fn insert(&mut self, path: PathBuf, entry: CacheEntry) {
+        self.new.insert(path, entry);
+    }
+
+    /// Writes all entries for files seen since the cache was loaded to the
+    /// specified path.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut w = Writer::default();
+        w.bytes(&MAGIC);
+        w.u32(VERSION);
+        w.u8(cfg!(feature = "emacs") as u8);
+        w.u32(self.new.len() as u32);
+        for (file, entry) in &self.new {
+            let timestamp = entry
+                .timestamp
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default();
+            w.str(&file.to_string_lossy());
+            w.u64(timestamp.as_secs());
+            w.u32(timestamp.subsec_nanos());
+            w.u64(entry.size);
+            w.u8(entry.all_names as u8);
+            w.u32(entry.fonts.len() as u32);
+            for font in &entry.fonts {
+                write_font(&mut w, font);
+            }
+            let issues: Vec<_> = entry.issues.iter().filter_map(encode_issue).collect();
+            w.u32(issues.len() as u32);
+            for (code, index) in issues {
+                w.u8(code);
+                w.u32(index);
+            }
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Write to a temporary file first so that readers never observe a
+        // partially written cache.
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, &w.buf)?;
+        fs::rename(&tmp, path)
+    }
+}
+
+fn read_entries(r: &mut Reader) -> Option<FxHashMap<PathBuf, CacheEntry>> {
+    if r.bytes(4)? != MAGIC || r.u32()? != VERSION {
+        return None;
+    }
+    if r.u8()? != cfg!(feature = "emacs") as u8 {
+        return None;
+    }
+    let count = r.u32()?;
+    let mut entries = FxHashMap::default();
+    for _ in 0..count {
+        let file = PathBuf::from(r.str()?);
+        let secs = r.u64()?;
+        let nanos = r.u32()?;
+        let timestamp = SystemTime::UNIX_EPOCH.checked_add(Duration::new(secs, nanos))?;
+        let size = r.u64()?;
+        let all_names = r.u8()? != 0;
+        let font_count = r.u32()?;
+        let mut fonts: Vec<FontInfo> = Vec::new();
+        for _ in 0..font_count {
+            let mut font = read_font(r)?;
+            // Named instances share the coverage of their variable font.
+            if let Some(base) = fonts.last() {
+                if font.instance.is_some() && base.coverage == font.coverage {
+                    font.coverage = base.coverage.clone();
+                }
+            }
+            fonts.push(font);
+        }
+        let issue_count = r.u32()?;
+        let mut issues = Vec::new();
+        for _ in 0..issue_count {
+            issues.push(read_issue(r)?);
+        }
+        entries.insert(
+            file,
+            CacheEntry {
+                timestamp,
+                size,
+                all_names,
+                fonts,
+                issues,
+            },
+        );
+    }
+    Some(entries)
+}
+
+fn write_font(w: &mut Writer, font: &FontInfo) {
+    w.u32(font.offset);
+    w.u32(font.index);
+    w.str(&font.name);
+    w.u32(font.attrs.0);
+    w.u32(font.revision);
+    w.u32(font.axes.len() as u32);
+    for axis in &font.axes {
+        w.u32(axis.tag);
+        w.u32(axis.min.to_bits());
+        w.u32(axis.default.to_bits());
+        w.u32(axis.max.to_bits());
+    }
+    w.str(&font.subfamily);
+    w.u32(font.instance.map_or(0, |index| index as u32 + 1));
+    w.u32(font.coords.len() as u32);
+    for coord in &font.coords {
+        w.u32(*coord as u16 as u32);
+    }
+    w.u32(font.spacing as u32);
+    w.u32(font.outline as u32 | (font.scalable as u32) << 1);
+    w.str(&font.foundry);
+    w.u32(font.color.bits() as u32);
+    w.u32(font.coverage.ranges().len() as u32);
+    for (start, end) in font.coverage.ranges() {
+        w.u32(*start);
+        w.u32(*end);
+    }
+    write_list(w, &font.aliases);
+    w.str(&font.postscript_name);
+    write_list(w, &font.full_names);
+    let names = font.all_names();
+    w.u32(names.len() as u32);
+    for name in names {
+        w.str(name);
+    }
+    w.u32(font.writing_systems.len() as u32);
+    for (script, language, features) in &font.writing_systems {
+        w.u32(*script);
+        w.u32(*language);
+        w.u32(features.len() as u32);
+        for feature in features {
+            w.u32(*feature);
+        }
+    }
+    #[cfg(feature = "emacs")]
+    {
+        write_strings(w, &font.supported_charsets);
+        write_strings(w, &font.supported_scripts);
+    }
+}
+
+fn read_font(r: &mut Reader) -> Option<FontInfo> {
+    let mut font = FontInfo {
+        offset: r.u32()?,
+        index: r.u32()?,
+        name: r.str()?.into(),
+        attrs: Attributes(r.u32()?),
+        ..Default::default()
+    };
+    let (stretch, weight, style) = font.attrs.parts();
+    font.stretch = stretch;
+    font.weight = weight;
+    font.style = style;
+    font.revision = r.u32()?;
+    let axis_count = r.u32()?;
+    for _ in 0..axis_count {
+        font.axes.push(AxisRange {
+            tag: r.u32()?,
+            min: f32::from_bits(r.u32()?),
+            default: f32::from_bits(r.u32()?),
+            max: f32::from_bits(r.u32()?),
+        });
+    }
+    font.subfamily = r.str()?.into();
+    font.instance = r.u32()?.checked_sub(1).map(|index| index as u16);
+    let coord_count = r.u32()?;
+    for _ in 0..coord_count {
+        font.coords.push(r.u32()? as u16 as i16);
+    }
+    font.spacing = Spacing::from_u8(r.u32()? as u8)?;
+    let flags = r.u32()?;
+    font.outline = flags & 1 != 0;
+    font.scalable = flags & 2 != 0;
+    font.foundry = r.str()?.into();
+    font.color = ColorFormats::from_bits(r.u32()? as u8);
+    let range_count = r.u32()?;
+    let mut ranges = Vec::new();
+    for _ in 0..range_count {
+        ranges.push((r.u32()?, r.u32()?));
+    }
+    font.coverage = Arc::new(Coverage::from_ranges(ranges));
+    font.aliases = read_list(r)?;
+    font.postscript_name = r.str()?.into();
+    font.full_names = read_list(r)?;
+    let name_count = r.u32()?;
+    for _ in 0..name_count {
+        font.all_names.push(r.str()?.into());
+    }
+    font.name_count = font.all_names.len();
+    let ws_count = r.u32()?;
+    for _ in 0..ws_count {
+        let script = r.u32()?;
+        let language = r.u32()?;
+        let feature_count = r.u32()?;
+        let mut features = Vec::new();
+        for _ in 0..feature_count {
+            features.push(r.u32()?);
+        }
+        font.writing_systems.push((script, language, features));
+    }
+    #[cfg(feature = "emacs")]
+    {
+        font.supported_charsets = read_strings(r)?;
+        font.supported_scripts = read_strings(r)?;
+    }
+    Some(font)
+}
+
+/// Returns the code and font index for an outcome produced while parsing a
+/// file.
+fn encode_issue(issue: &ScanOutcome) -> Option<(u8, u32)> {
+    Some(match issue {
+        ScanOutcome::InvalidFont { index } => (1, *index),
+        ScanOutcome::MissingFamilyName { index } => (2, *index),
+        ScanOutcome::UnsupportedFormat => (3, 0),
+        _ => return None,
+    })
+}
+
+fn read_issue(r: &mut Reader) -> Option<ScanOutcome> {
+    let code = r.u8()?;
+    let index = r.u32()?;
+    Some(match code {
+        1 => ScanOutcome::InvalidFont { index },
+        2 => ScanOutcome::MissingFamilyName { index },
+        3 => ScanOutcome::UnsupportedFormat,
+        _ => return None,
+    })
+}
+
+fn write_list(w: &mut Writer, strings: &[String]) {
+    w.u32(strings.len() as u32);
+    for s in strings {
+        w.str(s);
+    }
+}
+
+fn read_list(r: &mut Reader) -> Option<Vec<String>> {
+    let count = r.u32()?;
+    let mut strings = Vec::new();
+    for _ in 0..count {
+        strings.push(r.str()?.into());
+    }
+    Some(strings)
+}
+
+#[cfg(feature = "emacs")]
+fn write_strings(w: &mut Writer, strings: &[SmallString]) {
+    w.u32(strings.len() as u32);
+    for s in strings {
+        w.str(s.as_str());
+    }
+}
+
+#[cfg(feature = "emacs")]
+fn read_strings(r: &mut Reader) -> Option<Vec<SmallString>> {
+    let count = r.u32()?;
+    let mut strings = Vec::new();
+    for _ in 0..count {
+        strings.push(SmallString::new(r.str()?));
+    }
+    Some(strings)
+}
+
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn str(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.bytes(s.as_bytes());
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.bytes(8)?.try_into().ok()?))
+    }
+
+    fn str(&mut self) -> Option<&'a str> {
+        let len = self.u32()? as usize;
+        core::str::from_utf8(self.bytes(len)?).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_font::{self, TestFont};
+    use crate::FontLibrary;
+
+    #[test]
+    fn font_round_trip() {
+        let mut font = FontInfo::default();
+        font.offset = 12;
+        font.index = 1;
+        font.name = "Test Sans".into();
+        font.attrs = Attributes::new(
+            swash::Stretch::CONDENSED,
+            swash::Weight::BOLD,
+            swash::Style::Italic,
+        );
+        font.all_names = vec!["test sans".into(), "unused".into()];
+        font.revision = 0x0002_8000;
+        font.postscript_name = "TestSans-BoldItalic".into();
+        font.full_names = vec!["test sans bold italic".into()];
+        font.aliases = vec!["test sans bold".into()];
+        font.axes = vec![AxisRange {
+            tag: swash::tag_from_bytes(b"wght"),
+            min: 100.,
+            default: 400.,
+            max: 900.,
+        }];
+        font.subfamily = "Bold Italic".into();
+        font.instance = Some(2);
+        font.coords = vec![-16384, 9830];
+        font.spacing = Spacing::Dual;
+        font.scalable = true;
+        font.foundry = "PfEd".into();
+        font.coverage = Arc::new(Coverage::from_chars(0x20..0x7F));
+        font.color = ColorFormats::COLR_V0 | ColorFormats::SVG;
+        font.name_count = 1;
+        font.writing_systems = vec![(1, 2, vec![3, 4])];
+        let mut w = Writer::default();
+        write_font(&mut w, &font);
+        let read = read_font(&mut Reader::new(&w.buf)).unwrap();
+        assert_eq!(read.offset, 12);
+        assert_eq!(read.index, 1);
+        assert_eq!(read.name, "Test Sans");
+        assert_eq!(read.weight, swash::Weight::BOLD);
+        assert_eq!(read.stretch, swash::Stretch::CONDENSED);
+        assert_eq!(read.revision, 0x0002_8000);
+        assert_eq!(read.postscript_name, "TestSans-BoldItalic");
+        assert_eq!(read.full_names, font.full_names);
+        assert_eq!(read.aliases, font.aliases);
+        assert_eq!(read.axes, font.axes);
+        assert_eq!(read.subfamily, font.subfamily);
+        assert_eq!(read.instance, font.instance);
+        assert_eq!(read.coords, font.coords);
+        assert_eq!(read.spacing, font.spacing);
+        assert!(!read.outline && read.scalable);
+        assert_eq!(read.foundry, font.foundry);
+        assert_eq!(read.coverage, font.coverage);
+        assert_eq!(read.color, font.color);
+        assert_eq!(read.all_names(), &["test sans".to_string()]);
+        assert_eq!(read.writing_systems, font.writing_systems);
+    }
+
+    #[test]
+    fn reuse_unchanged_files() {
+        test_font::isolate();
+        let dir = test_font::temp_dir("cache-reuse");
+        let file = dir.join("fonts").join("Cached.ttf");
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        let data = TestFont::new("Cached").build();
+        fs::write(&file, &data).unwrap();
+        let path = dir.join("index.cache");
+        let revision = || {
+            let library = FontLibrary::builder()
+                .all_names(false)
+                .cache_file(&path)
+                .add_dir(dir.join("fonts"))
+                .build();
+            let index = library.index();
+            let revision = index.query("Cached", ()).unwrap().revision();
+            revision
+        };
+        assert_eq!(revision(), 0x10000);
+        // Entries of unchanged files are used instead of parsing the file, so
+        // an edited entry shows up in the index.
+        let mut cache = IndexCache::load(&path);
+        let modified = fs::metadata(&file).unwrap().modified().unwrap();
+        let mut entry = cache.take(&file, modified, data.len() as u64, false);
+        entry.as_mut().unwrap().fonts[0].revision = 0x20000;
+        cache.insert(file.clone(), entry.unwrap());
+        cache.save(&path).unwrap();
+        assert_eq!(revision(), 0x20000);
+        // Rewriting the file changes its modification time.
+        std::thread::sleep(Duration::from_millis(50));
+        fs::write(&file, &data).unwrap();
+        assert_eq!(revision(), 0x10000);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,11 +1,12 @@
 use super::family::parse_families;
 use super::index::*;
 use super::library::FontLibrary;
-use super::types::{FamilyId, FamilyKey, FontId, FontKey, SourceId};
+use super::types::{FamilyId, FamilyKey, FontId, FontKey, QueryAttributes, SourceId};
 use super::{shared_data::SharedData, Font};
 #[cfg(feature = "emacs")]
 use crate::emacs::FontSpec;
-use crate::util::fxhash::FxHashMap;
+use crate::util::fxhash::{FxHashMap, FxHasher};
+use core::hash::{Hash, Hasher};
 use std::sync::Arc;
 use swash::proxy::CharmapProxy;
 use swash::text::{
@@ -13,7 +14,7 @@ use swash::text::{
     Cjk, Language, Script,
 };
 use swash::{Attributes, Synthesis};
-pub type FontGroupKey = (u64, Attributes);
+pub type FontGroupKey = (u64, QueryAttributes);
 type Epoch = u64;
 
 /// Identifier for a cached font group.
@@ -22,6 +23,9 @@ pub struct FontGroupId(pub u64);
 
 const MAX_INLINE: usize = 6;
 
+/// Maximum number of clusters with a cached last resort fallback font.
+const MAX_COVERAGE_ENTRIES: usize = 256;
+
 pub struct FontContext {
     library: FontLibrary,
     fonts: FontCache,
@@ -30,12 +34,7 @@ pub struct FontContext {
 
 impl FontContext {
     pub fn new(library: FontLibrary) -> Self {
-        let index = library.inner.index.read().unwrap().clone();
-        let fonts = FontCache {
-            index,
-            sources: FxHashMap::default(),
-            epoch: 0,
-        };
+        let fonts = FontCache::new(library.clone());
         Self {
             library,
             fonts,
@@ -50,13 +49,41 @@ impl FontContext {
 
     /// Resets font group caches and state. This should be called at the end
     /// of every layout session.
+    ///
+    /// If the library has been refreshed, this also switches to the new
+    /// index.
     pub fn reset_group_state(&mut self) {
         self.groups.reset();
+        self.update();
     }
 
-    /// Registers a font group.
-    pub fn register_group(&mut self, families: &str, key: u64, attrs: Attributes) -> FontGroupId {
-        self.groups.get(&self.fonts, families, key, attrs)
+    /// Switches to the current index of the library if it has been
+    /// refreshed. Returns true if the index changed.
+    ///
+    /// All previously registered font groups are invalidated when this
+    /// happens. Their identifiers are not reused, so selecting one of them
+    /// selects an empty group.
+    pub fn update(&mut self) -> bool {
+        if self.fonts.update() {
+            self.groups = GroupCache {
+                next_id: self.groups.next_id,
+                ..GroupCache::default()
+            };
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Registers a font group. Color formats in the attributes restrict the
+    /// fonts selected from the named families but not the fallbacks.
+    pub fn register_group(
+        &mut self,
+        families: &str,
+        key: u64,
+        attrs: impl Into<QueryAttributes>,
+    ) -> FontGroupId {
+        self.groups.get(&self.fonts, families, key, attrs.into())
     }
 
     /// Selects a font group for subsequent cluster mapping operations.
@@ -110,7 +137,15 @@ impl FontContext {
         if !self.groups.state.fallbacks_ready {
             self.groups.fill_fallbacks(&self.fonts);
         }
-        for &family in &self.groups.state.fallbacks {
+        // Generic families in the group are followed by their configured
+        // fallback chains and then by the script fallbacks.
+        let chain_len = self.groups.state.chain.len();
+        for i in 0..chain_len + self.groups.state.fallbacks.len() {
+            let family = if i < chain_len {
+                self.groups.state.chain[i]
+            } else {
+                self.groups.state.fallbacks[i - chain_len]
+            };
             let entry = match self.groups.state.fallback_map.get_mut(&(family, attrs)) {
                 Some(entry) => entry,
                 _ => match self.fonts.query(family, attrs) {
@@ -138,32 +173,66 @@ impl FontContext {
                 None => continue,
             }
         }
+        // As a last resort, search all fonts for one that supports every
+        // character in the cluster.
+        if let Some(entry) = self.groups.coverage_fallback(&self.fonts, cluster, attrs) {
+            if let Some((font, status)) =
+                entry.map_cluster(&mut self.fonts, cluster, synthesis, best.is_none())
+            {
+                if status == Status::Complete {
+                    return Some(font);
+                }
+                best = Some(font);
+            }
+        }
         best
     }
 }
 
 pub struct FontCache {
     pub index: Arc<StaticIndex>,
+    library: FontLibrary,
+    generation: u64,
     sources: FxHashMap<SourceId, Option<(SharedData, Epoch)>>,
     epoch: Epoch,
 }
 
 impl FontCache {
     pub fn default() -> Self {
-        let index = StaticIndex::global().clone();
+        Self::new(FontLibrary::global().clone())
+    }
+
+    /// Creates a font cache for the specified library.
+    pub fn new(library: FontLibrary) -> Self {
+        let (index, generation) = library.current();
         FontCache {
             index,
+            library,
+            generation,
             sources: FxHashMap::default(),
             epoch: 0,
         }
     }
 
+    /// Switches to the current index of the library if it has been
+    /// refreshed. Returns true if the index changed.
+    pub fn update(&mut self) -> bool {
+        if self.library.generation() == self.generation {
+            return false;
+        }
+        let (index, generation) = self.library.current();
+        self.index = index;
+        self.generation = generation;
+        self.sources.clear();
+        true
+    }
+
     /// Returns a font entry that matches the specified family and
     /// attributes.
     pub fn query<'a>(
         &'a self,
         family: impl Into<FamilyKey<'a>>,
-        attributes: impl Into<Attributes>,
+        attributes: impl Into<QueryAttributes>,
     ) -> Option<FontEntry<'a>> {
         self.index.query(family, attributes)
     }
@@ -182,39 +251,50 @@ impl FontCache {
         self.index.font_by_id(id)
     }
 
-    /// Returns a font matching the specified key.
+    /// Returns a font matching the specified key. Variable fonts requested
+    /// with a descriptor are instanced for its attributes.
     pub fn get<'k>(&mut self, key: impl Into<FontKey<'k>>) -> Option<Font> {
-        let (source_id, offset, attributes, key) = match key.into() {
-            FontKey::Id(id) => {
-                let font = self.font_by_id(id)?;
-                (
-                    font.source().id(),
-                    font.offset(),
-                    font.attributes(),
-                    font.cache_key(),
-                )
-            }
+        match key.into() {
+            FontKey::Id(id) => self.load(id, None),
             FontKey::Descriptor(family, attrs) => {
-                let font = self.query(family, attrs)?;
-                (
-                    font.source().id(),
-                    font.offset(),
-                    font.attributes(),
-                    font.cache_key(),
-                )
+                let id = self.query(family, attrs)?.id();
+                self.load(id, Some(attrs))
             }
+        }
+    }
+
+    /// Loads a font, instancing variable fonts for the requested attributes.
+    fn load(&mut self, id: FontId, attrs: Option<Attributes>) -> Option<Font> {
+        let font = self.font_by_id(id)?;
+        let (source_id, offset, key) = (font.source().id(), font.offset(), font.cache_key());
+        let coords = font.coords().to_vec();
+        let (attributes, settings) = match attrs {
+            Some(attrs) => (font.selector(attrs).1, font.variation_settings(attrs)),
+            None => (font.attributes(), Vec::new()),
+        };
+        // Named instances use their own coordinates.
+        let instance = |data| {
+            let mut font = Font {
+                data,
+                offset,
+                attributes,
+                key,
+                coords: coords.clone(),
+            };
+            if !settings.is_empty() {
+                font.coords = font
+                    .variations()
+                    .normalized_coords(settings.iter().copied())
+                    .collect();
+            }
+            font
         };
         let epoch = self.epoch;
         match self.sources.get_mut(&source_id) {
             Some(data) => {
                 return data.as_mut().map(|d| {
                     d.1 = epoch;
-                    Font {
-                        data: d.0.clone(),
-                        offset,
-                        attributes,
-                        key,
-                    }
+                    instance(d.0.clone())
                 })
             }
             _ => {}
@@ -223,12 +303,7 @@ impl FontCache {
         match source.get() {
             Some(data) => {
                 self.sources.insert(source_id, Some((data.clone(), epoch)));
-                Some(Font {
-                    data,
-                    offset,
-                    attributes,
-                    key,
-                })
+                Some(instance(data))
             }
             _ => {
                 self.sources.insert(source_id, None);
@@ -255,6 +330,20 @@ struct GroupCache {
     key_map: FxHashMap<FontGroupKey, CachedGroup>,
     /// Temporary storage for parsing a user font descriptor.
     tmp: Vec<(FontId, Attributes)>,
+    /// Temporary storage for the generic fallback chain of a descriptor.
+    tmp_chain: Vec<FamilyId>,
+    /// Map from descriptor identifier to the fallback chains of the
+    /// generic families it names.
+    chains: FxHashMap<FontGroupId, Vec<FamilyId>>,
+    /// Map from a hash of the characters of a cluster and attributes to the
+    /// closest font that supports the characters, for the last resort
+    /// fallback. The least recently used entry is evicted when the map is
+    /// full.
+    coverage_map: FxHashMap<(u64, Attributes), CoverageEntry>,
+    /// Counter for the last use of coverage map entries.
+    coverage_epoch: Epoch,
+    /// Temporary storage for the characters of a cluster.
+    tmp_chars: Vec<char>,
     /// Next descriptor identifier.
     next_id: u64,
     /// Map from descriptor identifier to the list of cached fonts. This
@@ -281,10 +370,14 @@ struct GroupCacheState {
     fallback_map: FxHashMap<(FamilyId, Attributes), CachedFont>,
     /// Current list of fallback families.
     fallbacks: Vec<FamilyId>,
+    /// Fallback chain of the generic families in the selected group.
+    chain: Vec<FamilyId>,
     /// True if we've attempted to load an emoji font.
     emoji_ready: bool,
     /// Cached emoji font.
     emoji: Option<CachedFont>,
+    /// Transient cache of last resort fallback fonts.
+    coverage_fonts: FxHashMap<(FontId, Attributes), CachedFont>,
 }
 
 impl Default for GroupCacheState {
@@ -296,8 +389,10 @@ impl Default for GroupCacheState {
             fallbacks_ready: true,
             fallback_map: FxHashMap::default(),
             fallbacks: Vec::new(),
+            chain: Vec::new(),
             emoji_ready: false,
             emoji: None,
+            coverage_fonts: FxHashMap::default(),
         }
     }
 }
@@ -310,16 +405,25 @@ impl GroupCacheState {
         self.fallbacks_ready = true;
         self.fallback_map.clear();
         self.fallbacks.clear();
+        self.chain.clear();
         self.emoji_ready = false;
         self.emoji = None;
+        self.coverage_fonts.clear();
     }
 }
 
 impl GroupCache {
     /// Returns a font group identifier for the specified families and attributes.
-    fn get(&mut self, fonts: &FontCache, names: &str, key: u64, attrs: Attributes) -> FontGroupId {
+    fn get(
+        &mut self,
+        fonts: &FontCache,
+        names: &str,
+        key: u64,
+        query: QueryAttributes,
+    ) -> FontGroupId {
         use std::collections::hash_map::Entry;
-        let key = (key, attrs);
+        let attrs = query.attributes;
+        let key = (key, query);
         // Fast path for a descriptor we've already seen.
         match self.key_map.get_mut(&key) {
             Some(item) => {
@@ -348,19 +452,28 @@ impl GroupCache {
         }
         // Parse the descriptor and collect the font identifiers.
         self.tmp.clear();
+        self.tmp_chain.clear();
         for family in parse_families(names) {
-            match fonts.query(family, attrs).map(|f| f.selector(attrs)) {
+            if let FamilyKey::Generic(generic) = family {
+                for id in fonts.index.generic_chain(generic) {
+                    if !self.tmp_chain.contains(id) {
+                        self.tmp_chain.push(*id);
+                    }
+                }
+            }
+            match fonts.query(family, query).map(|f| f.selector(attrs)) {
                 Some(sel) => self.tmp.push((sel.0, sel.1)),
                 _ => {}
             }
         }
         // Slow path: linear search.
         for (item_key, item) in &self.key_map {
-            if item_key.1 != attrs {
+            if item_key.1 != query {
                 continue;
             }
             let existing = item.data.get();
-            if existing == &self.tmp {
+            let chain = self.chains.get(&item.id).map_or(&[][..], |chain| chain);
+            if existing == &self.tmp && chain == &self.tmp_chain[..] {
                 match self.font_map.entry(item.id) {
                     Entry::Occupied(..) => {}
                     Entry::Vacant(e) => {
@@ -401,6 +514,9 @@ impl GroupCache {
                 end,
             },
         );
+        if !self.tmp_chain.is_empty() {
+            self.chains.insert(id, self.tmp_chain.clone());
+        }
         let desc = CachedGroup {
             id,
             epoch: fonts.epoch,
@@ -419,6 +535,10 @@ impl GroupCache {
             Some(fonts) => self.state.fonts = *fonts,
             _ => self.state.fonts = CachedFontList::default(),
         }
+        self.state.chain.clear();
+        if let Some(chain) = self.chains.get(&id) {
+            self.state.chain.extend_from_slice(chain);
+        }
         self.state.id = id;
     }
 
@@ -454,6 +574,72 @@ impl GroupCache {
         self.state.emoji.as_mut()
     }
 
+    /// Returns the font closest to the attributes that supports all
+    /// characters in the cluster. Results are cached per cluster.
+    fn coverage_fallback(
+        &mut self,
+        fonts: &FontCache,
+        cluster: &CharCluster,
+        attrs: Attributes,
+    ) -> Option<&mut CachedFont> {
+        self.tmp_chars.clear();
+        self.tmp_chars.extend(
+            cluster
+                .chars()
+                .iter()
+                .filter(|ch| !ch.ignorable && !ch.ch.is_control())
+                .map(|ch| ch.ch),
+        );
+        if self.tmp_chars.is_empty() {
+            return None;
+        }
+        let mut hasher = FxHasher::default();
+        self.tmp_chars.hash(&mut hasher);
+        let key = (hasher.finish(), attrs);
+        self.coverage_epoch += 1;
+        let epoch = self.coverage_epoch;
+        let id = match self.coverage_map.get_mut(&key) {
+            Some(entry) if entry.chars == self.tmp_chars => {
+                entry.epoch = epoch;
+                entry.font
+            }
+            _ => {
+                let id = fonts
+                    .index
+                    .font_covering_all(&self.tmp_chars, attrs)
+                    .map(|font| font.id());
+                if self.coverage_map.len() >= MAX_COVERAGE_ENTRIES
+                    && !self.coverage_map.contains_key(&key)
+                {
+                    let oldest = self
+                        .coverage_map
+                        .iter()
+                        .min_by_key(|(_, entry)| entry.epoch)
+                        .map(|(key, _)| *key);
+                    if let Some(oldest) = oldest {
+                        self.coverage_map.remove(&oldest);
+                    }
+                }
+                self.coverage_map.insert(
+                    key,
+                    CoverageEntry {
+                        chars: self.tmp_chars.clone(),
+                        font: id,
+                        epoch,
+                    },
+                );
+                id
+            }
+        };
+        let font = fonts.font_by_id(id?)?;
+        Some(
+            self.state
+                .coverage_fonts
+                .entry((font.id(), attrs))
+                .or_insert_with(|| font.selector(attrs).into()),
+        )
+    }
+
     /// Clears all transient state.
     fn reset(&mut self) {
         self.state.reset();
@@ -547,8 +733,18 @@ struct CachedFontList {
     end: usize,
 }
 
+/// Last resort fallback font for the characters of a cluster.
+struct CoverageEntry {
+    chars: Vec<char>,
+    font: Option<FontId>,
+    /// Value of the coverage epoch when the entry was last used.
+    epoch: Epoch,
+}
+
 struct CachedFont {
     id: FontId,
+    /// Requested attributes for which variable fonts are instanced.
+    attrs: Attributes,
     font: Option<Font>,
     charmap: CharmapProxy,
     synth: Synthesis,
@@ -570,7 +766,7 @@ impl CachedFont {
         let font = match &self.font {
             Some(font) => font,
             None => {
-                let font = fonts.get(self.id);
+                let font = fonts.load(self.id, Some(self.attrs));
                 let font = match font {
                     Some(f) => f,
                     _ => {
@@ -599,6 +795,7 @@ impl From<(FontId, Attributes, Attributes)> for CachedFont {
         let synth = v.1.synthesize(v.2);
         Self {
             id: v.0,
+            attrs: v.2,
             font: None,
             charmap: CharmapProxy::default(),
             synth,
@@ -606,3 +803,137 @@ impl From<(FontId, Attributes, Attributes)> for CachedFont {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ColorFormats;
+    use crate::util::test_font::{self, TestFont};
+    use std::fs;
+    use swash::text::cluster::{Parser, Token};
+    use swash::Weight;
+
+    fn cluster(ch: char) -> CharCluster {
+        let mut cluster = CharCluster::new();
+        let token = Token {
+            ch,
+            offset: 0,
+            len: 1,
+            info: ch.into(),
+            data: 0,
+        };
+        Parser::new(Script::Latin, core::iter::once(token)).next(&mut cluster);
+        cluster
+    }
+
+    #[test]
+    fn variable_weight() {
+        test_font::isolate();
+        let font = TestFont {
+            axes: vec![(*b"wght", 100., 400., 900.)],
+            ..TestFont::new("Variable")
+        };
+        let library = FontLibrary::builder().add_data(font.build()).build();
+        let mut context = FontContext::new(library);
+        let attrs = Attributes::new(Default::default(), Weight(700), Default::default());
+        let group = context.register_group("Variable", 0, attrs);
+        context.select_group(group);
+        let mut synth = Synthesis::default();
+        let font = context.map_cluster(&mut cluster('A'), &mut synth).unwrap();
+        assert_eq!(font.requested_attributes().weight(), Weight(700));
+        // 700 lies 3/5 of the way from the default to the maximum weight.
+        assert_eq!(font.coords(), &[9831]);
+        assert!(!synth.embolden());
+    }
+
+    #[test]
+    fn coverage_fallback() {
+        test_font::isolate();
+        let cyrillic = TestFont {
+            chars: ('а'..='я').map(|ch| (ch, 600)).collect(),
+            ..TestFont::new("Cyrillic")
+        };
+        let library = FontLibrary::builder()
+            .add_data(TestFont::new("Latin").build())
+            .add_data(cyrillic.build())
+            .build();
+        let mut context = FontContext::new(library);
+        let group = context.register_group("Latin", 0, Attributes::default());
+        context.select_group(group);
+        let mut synth = Synthesis::default();
+        for _ in 0..2 {
+            let font = context.map_cluster(&mut cluster('ж'), &mut synth).unwrap();
+            assert_ne!(font.charmap().map('ж'), 0);
+        }
+        assert_eq!(context.groups.coverage_map.len(), 1);
+        // Clusters that no font supports are cached too, up to the limit.
+        for ch in (0x4E00..0x4F00).filter_map(char::from_u32) {
+            context.map_cluster(&mut cluster(ch), &mut synth);
+        }
+        assert_eq!(context.groups.coverage_map.len(), MAX_COVERAGE_ENTRIES);
+        let font = context.map_cluster(&mut cluster('ж'), &mut synth).unwrap();
+        assert_ne!(font.charmap().map('ж'), 0);
+    }
+
+    #[test]
+    fn color_group() {
+        test_font::isolate();
+        let color = TestFont {
+            subfamily: "Bold".into(),
+            weight: 700,
+            tables: vec![(*b"SVG ", vec![0; 10])],
+            ..TestFont::new("Mixed")
+        };
+        let library = FontLibrary::builder()
+            .add_data(TestFont::new("Mixed").build())
+            .add_data(color.build())
+            .build();
+        let index = library.index();
+        let subfamily = |query: QueryAttributes| {
+            let font = index.query("Mixed", query)?;
+            Some(font.subfamily_name().to_string())
+        };
+        assert_eq!(subfamily(().into()).as_deref(), Some("Regular"));
+        let svg = QueryAttributes::new(()).color(ColorFormats::SVG | ColorFormats::SBIX);
+        assert_eq!(subfamily(svg).as_deref(), Some("Bold"));
+        assert_eq!(
+            subfamily(QueryAttributes::new(()).color(ColorFormats::CBDT)),
+            None
+        );
+        let mut context = FontContext::new(library);
+        let plain = context.register_group("Mixed", 0, ());
+        let color = context.register_group("Mixed", 0, svg);
+        assert_ne!(plain, color);
+        let mut synth = Synthesis::default();
+        for (group, weight) in [(plain, Weight::NORMAL), (color, Weight::BOLD)] {
+            context.select_group(group);
+            let font = context.map_cluster(&mut cluster('A'), &mut synth).unwrap();
+            assert_eq!(font.requested_attributes().weight(), weight);
+        }
+    }
+
+    #[test]
+    fn refresh_groups() {
+        test_font::isolate();
+        let dir = test_font::temp_dir("context-refresh");
+        fs::write(dir.join("Alpha.ttf"), TestFont::new("Alpha").build()).unwrap();
+        let library = FontLibrary::builder().add_dir(&dir).build();
+        let mut context = FontContext::new(library.clone());
+        let stale = context.register_group("Alpha", 0, ());
+        assert!(!context.update());
+        fs::write(dir.join("Beta.ttf"), TestFont::new("Beta").build()).unwrap();
+        library.refresh();
+        assert!(context.update());
+        assert!(!context.update());
+        let group = context.register_group("Beta", 0, ());
+        assert_ne!(group, stale);
+        // Groups registered before the update select no fonts.
+        context.select_group(stale);
+        let fonts = context.groups.state.fonts;
+        assert_eq!(fonts.start, fonts.end);
+        context.select_group(group);
+        let fonts = context.groups.state.fonts;
+        assert_eq!(fonts.end - fonts.start, 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,5 +1,6 @@
 use isolang::Language;
 use once_cell::sync::Lazy as LazyLock;
+use std::convert::TryFrom;
 use swash::{Stretch, Style, Weight};
 
 use crate::{
@@ -405,23 +406,11 @@ pub struct FontSpec {
 // or dual.  It can be either a number (0 for proportional, 90 for dual,
 // 100 for mono, 110 for charcell) or a 1-letter symbol: P, D, M,
 // or C (lower-case variants are also accepted).
-#[derive(Clone, Debug)]
-pub enum Spacing {
-    Mono = 0,
-    Proportional = 90,
-    Charcell = 100,
-    Dual = 110,
-}
+pub use crate::types::Spacing;
 
 impl Spacing {
     pub fn from_number(num: i32) -> Option<Self> {
-        match num {
-            0 => Some(Self::Proportional),
-            90 => Some(Self::Dual),
-            100 => Some(Self::Mono),
-            110 => Some(Self::Charcell),
-            _ => None,
-        }
+        u8::try_from(num).ok().and_then(Self::from_u8)
     }
 
     pub fn from_symbol(sym: &str) -> Option<Self> {
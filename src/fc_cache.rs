@@ -0,0 +1,557 @@
+//! Reader for fontconfig's binary per-directory cache files.
+//!
+//! fontconfig writes one cache file per font directory. Each file holds
+//! the patterns of all fonts in the directory along with the list of its
+//! subdirectories. The files are images of in-memory structures where
+//! pointers are stored as offsets, so only caches written for the native
+//! architecture can be read.
+
+use crate::types::Spacing;
+use crate::util::fxhash::FxHashMap;
+use core::convert::{TryFrom, TryInto};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Architecture suffix in cache file names for the current target.
+#[cfg(all(target_pointer_width = "64", target_endian = "little"))]
+const ARCH: Option<&str> = Some("le64");
+#[cfg(all(target_pointer_width = "64", target_endian = "big"))]
+const ARCH: Option<&str> = Some("be64");
+#[cfg(not(target_pointer_width = "64"))]
+const ARCH: Option<&str> = None;
+
+const MAGIC_MMAP: u32 = 0xFC02FC04;
+/// Size of a value list node, which bounds the length of the lists in an
+/// image.
+const VALUE_LIST_SIZE: usize = 32;
+const MIN_VERSION: i32 = 7;
+const MAX_VERSION: i32 = 9;
+
+// Object identifiers from fcobjs.h.
+const FAMILY: i32 = 1;
+const FAMILYLANG: i32 = 2;
+const STYLE: i32 = 3;
+const STYLELANG: i32 = 4;
+const FULLNAME: i32 = 5;
+const FULLNAMELANG: i32 = 6;
+const SLANT: i32 = 7;
+const WEIGHT: i32 = 8;
+const WIDTH: i32 = 9;
+const SPACING: i32 = 13;
+const FOUNDRY: i32 = 14;
+const FILE: i32 = 21;
+const INDEX: i32 = 22;
+const OUTLINE: i32 = 24;
+const SCALABLE: i32 = 25;
+const CHARSET: i32 = 33;
+const FONTVERSION: i32 = 35;
+const POSTSCRIPT_NAME: i32 = 46;
+const COLOR: i32 = 47;
+const VARIABLE: i32 = 50;
+
+// Value types from fontconfig.h.
+const TYPE_INTEGER: i32 = 1;
+const TYPE_DOUBLE: i32 = 2;
+const TYPE_STRING: i32 = 3;
+const TYPE_BOOL: i32 = 4;
+const TYPE_CHARSET: i32 = 6;
+const TYPE_RANGE: i32 = 9;
+
+/// Contents of the cache file for a single directory.
+pub struct CachedDir {
+    pub dir: PathBuf,
+    pub subdirs: Vec<PathBuf>,
+    pub fonts: Vec<CachedFont>,
+    checksum: i32,
+    checksum_nano: i64,
+}
+
+impl CachedDir {
+    /// Returns true if the directory has not been modified since the cache
+    /// was written.
+    pub fn is_current(&self) -> bool {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            match fs::metadata(&self.dir) {
+                Ok(metadata) => {
+                    self.checksum == metadata.mtime() as i32
+                        && self.checksum_nano == metadata.mtime_nsec()
+                }
+                Err(_) => false,
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            false
+        }
+    }
+}
+
+/// Pattern for a single font in a cache file.
+#[cfg_attr(test, derive(Default))]
+pub struct CachedFont {
+    pub file: PathBuf,
+    /// Face index in the low 16 bits and named instance in the high bits.
+    pub index: u32,
+    /// Family names paired with their languages.
+    pub families: Vec<(String, Option<String>)>,
+    /// Style names paired with their languages.
+    pub styles: Vec<(String, Option<String>)>,
+    /// Full names paired with their languages.
+    pub full_names: Vec<(String, Option<String>)>,
+    pub postscript_name: Option<String>,
+    /// Weight on the fontconfig scale as a range for variable fonts.
+    pub weight: Option<(f64, f64)>,
+    /// Width as a percentage of normal.
+    pub width: Option<(f64, f64)>,
+    pub slant: Option<i32>,
+    pub spacing: Spacing,
+    pub outline: bool,
+    pub scalable: bool,
+    pub foundry: Option<String>,
+    /// True for the pattern of a variable font as a whole, as opposed to
+    /// its named instances.
+    pub variable: bool,
+    /// True if the font has color glyphs.
+    pub color: bool,
+    /// Font revision from the `head` table as a 16.16 fixed point number.
+    pub revision: u32,
+    pub charset: Option<CharSet>,
+}
+
+/// Set of characters supported by a font.
+#[derive(Clone, Default)]
+pub struct CharSet {
+    /// Pages of 256 characters keyed by the high bits of the codepoint.
+    pages: Vec<(u16, [u32; 8])>,
+}
+
+impl CharSet {
+    /// Returns an iterator over the characters in the set in order.
+    pub fn chars(&self) -> impl Iterator<Item = u32> + '_ {
+        self.pages.iter().flat_map(|(number, bits)| {
+            let base = (*number as u32) << 8;
+            (0..256u32)
+                .filter(move |i| bits[(i >> 5) as usize] & (1 << (i & 31)) != 0)
+                .map(move |i| base + i)
+        })
+    }
+
+    /// Returns true if the character is in the set.
+    pub fn contains(&self, ch: u32) -> bool {
+        let page = match u16::try_from(ch >> 8) {
+            Ok(page) => page,
+            Err(_) => return false,
+        };
+        match self
+            .pages
+            .binary_search_by_key(&page, |(number, _)| *number)
+        {
+            Ok(i) => {
+                let bits = self.pages[i].1[((ch & 0xFF) >> 5) as usize];
+                bits & (1 << (ch & 31)) != 0
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// Cache files for all directories found in a set of cache directories.
+#[derive(Default)]
+pub struct FcCaches {
+    dirs: FxHashMap<PathBuf, CachedDir>,
+}
+
+impl FcCaches {
+    /// Loads all cache files for the current architecture in the specified
+    /// cache directories. When a font directory has several cache files,
+    /// the first one that is current is used.
+    pub fn load(cache_dirs: &[PathBuf]) -> Self {
+        let mut caches = Self::default();
+        let arch = match ARCH {
+            Some(arch) => arch,
+            None => return caches,
+        };
+        for cache_dir in cache_dirs {
+            let entries = match fs::read_dir(cache_dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                let name = entry.file_name();
+                let name = match name.to_str() {
+                    Some(name) => name,
+                    None => continue,
+                };
+                let version = name
+                    .split_once(".cache-")
+                    .filter(|(stem, _)| stem.ends_with(&format!("-{}", arch)))
+                    .and_then(|(_, version)| version.parse::<i32>().ok());
+                if !version.is_some_and(|v| (MIN_VERSION..=MAX_VERSION).contains(&v)) {
+                    continue;
+                }
+                let data = match fs::read(entry.path()) {
+                    Ok(data) => data,
+                    Err(_) => continue,
+                };
+                if let Some(dir) = parse(&data) {
+                    let replace = match caches.dirs.get(&dir.dir) {
+                        Some(existing) => !existing.is_current() && dir.is_current(),
+                        None => true,
+                    };
+                    if replace {
+                        caches.dirs.insert(dir.dir.clone(), dir);
+                    }
+                }
+            }
+        }
+        caches
+    }
+
+    /// Returns the cache for the directory if it is current.
+    pub fn get(&self, dir: &Path) -> Option<&CachedDir> {
+        self.dirs.get(dir).filter(|dir| dir.is_current())
+    }
+}
+
+#[cfg(test)]
+impl FcCaches {
+    /// Adds a current cache for a directory.
+    pub fn insert(&mut self, dir: &Path, subdirs: Vec<PathBuf>, fonts: Vec<CachedFont>) {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = fs::metadata(dir).unwrap();
+        let cached = CachedDir {
+            dir: dir.into(),
+            subdirs,
+            fonts,
+            checksum: metadata.mtime() as i32,
+            checksum_nano: metadata.mtime_nsec(),
+        };
+        self.dirs.insert(dir.into(), cached);
+    }
+}
+
+/// Bounds checked access to the cache image.
+struct Image<'a>(&'a [u8]);
+
+impl Image<'_> {
+    fn bytes<const N: usize>(&self, offset: usize) -> Option<[u8; N]> {
+        self.0.get(offset..offset.checked_add(N)?)?.try_into().ok()
+    }
+
+    fn i32(&self, offset: usize) -> Option<i32> {
+        self.bytes(offset).map(i32::from_ne_bytes)
+    }
+
+    fn i64(&self, offset: usize) -> Option<i64> {
+        self.bytes(offset).map(i64::from_ne_bytes)
+    }
+
+    fn f64(&self, offset: usize) -> Option<f64> {
+        self.bytes(offset).map(f64::from_ne_bytes)
+    }
+
+    /// Reads a plain offset relative to `base`.
+    fn offset(&self, base: usize, offset: usize) -> Option<usize> {
+        let value = self.i64(offset)?;
+        base.checked_add_signed(value as isize)
+            .filter(|&target| target < self.0.len())
+    }
+
+    /// Reads a pointer stored as an offset relative to `base` with the low
+    /// bit set. Returns `None` for null or unencoded pointers.
+    fn pointer(&self, base: usize, offset: usize) -> Option<usize> {
+        let value = self.i64(offset)?;
+        if value & 1 == 0 {
+            return None;
+        }
+        base.checked_add_signed((value & !1) as isize)
+            .filter(|&target| target < self.0.len())
+    }
+
+    fn string(&self, offset: usize) -> Option<String> {
+        let bytes = self.0.get(offset..)?;
+        let len = bytes.iter().position(|&b| b == 0)?;
+        String::from_utf8(bytes[..len].to_vec()).ok()
+    }
+}
+
+/// Parses a cache file.
+fn parse(data: &[u8]) -> Option<CachedDir> {
+    let image = Image(data);
+    let magic = image.i32(0)? as u32;
+    let version = image.i32(4)?;
+    if magic != MAGIC_MMAP || !(MIN_VERSION..=MAX_VERSION).contains(&version) {
+        return None;
+    }
+    if image.i64(8)? as usize != data.len() {
+        return None;
+    }
+    let dir = PathBuf::from(image.string(image.offset(0, 16)?)?);
+    let dirs = image.offset(0, 24)?;
+    let dirs_count = image.i32(32)?.max(0) as usize;
+    let mut subdirs = Vec::with_capacity(dirs_count);
+    for i in 0..dirs_count {
+        let subdir = image.offset(dirs, dirs + i * 8)?;
+        subdirs.push(PathBuf::from(image.string(subdir)?));
+    }
+    let set = image.offset(0, 40)?;
+    let checksum = image.i32(48)?;
+    let checksum_nano = image.i64(56)?;
+    let font_count = image.i32(set)?.max(0) as usize;
+    let patterns = image.pointer(set, set + 8);
+    let mut fonts = Vec::with_capacity(font_count);
+    for i in 0..font_count {
+        let pattern = match patterns.and_then(|array| image.pointer(set, array + i * 8)) {
+            Some(pattern) => pattern,
+            None => continue,
+        };
+        if let Some(font) = parse_pattern(&image, pattern, &dir) {
+            fonts.push(font);
+        }
+    }
+    Some(CachedDir {
+        dir,
+        subdirs,
+        fonts,
+        checksum,
+        checksum_nano,
+    })
+}
+
+/// Value of a pattern element.
+enum Value {
+    Number(f64),
+    Range(f64, f64),
+    String(String),
+    CharSet(CharSet),
+}
+
+fn parse_pattern(image: &Image, pattern: usize, dir: &Path) -> Option<CachedFont> {
+    let count = image.i32(pattern)?.max(0) as usize;
+    let elts = image.offset(pattern, pattern + 8)?;
+    let mut file = None;
+    let mut index = 0;
+    let mut families = Vec::new();
+    let mut langs = Vec::new();
+    let mut styles = Vec::new();
+    let mut style_langs = Vec::new();
+    let mut full_names = Vec::new();
+    let mut full_name_langs = Vec::new();
+    let mut postscript_name = None;
+    let mut weight = None;
+    let mut width = None;
+    let mut slant = None;
+    let mut spacing = Spacing::Proportional;
+    let mut outline = false;
+    let mut scalable = false;
+    let mut foundry = None;
+    let mut revision = 0;
+    let mut variable = false;
+    let mut color = false;
+    let mut charset = None;
+    for i in 0..count {
+        let elt = elts + i * 16;
+        let object = image.i32(elt)?;
+        let mut node = image.pointer(elt, elt + 8);
+        // A list with more nodes than fit in the image has a cycle.
+        let mut steps = image.0.len() / VALUE_LIST_SIZE;
+        while let Some(list) = node {
+            steps = steps.checked_sub(1)?;
+            let value = parse_value(image, list + 8);
+            node = image.pointer(list, list);
+            let value = match value {
+                Some(value) => value,
+                None => continue,
+            };
+            match (object, value) {
+                (FAMILY, Value::String(name)) => families.push(name),
+                (FAMILYLANG, Value::String(lang)) => langs.push(lang),
+                (STYLE, Value::String(name)) => styles.push(name),
+                (STYLELANG, Value::String(lang)) => style_langs.push(lang),
+                (FULLNAME, Value::String(name)) => full_names.push(name),
+                (FULLNAMELANG, Value::String(lang)) => full_name_langs.push(lang),
+                (POSTSCRIPT_NAME, Value::String(name)) => postscript_name = Some(name),
+                (FILE, Value::String(path)) if file.is_none() => file = Some(dir.join(path)),
+                (INDEX, Value::Number(n)) => index = n as u32,
+                (SLANT, Value::Number(n)) if slant.is_none() => slant = Some(n as i32),
+                (WEIGHT, value) if weight.is_none() => weight = range(value),
+                (WIDTH, value) if width.is_none() => width = range(value),
+                (SPACING, Value::Number(n)) => {
+                    spacing = Spacing::from_u8(n as u8).unwrap_or_default()
+                }
+                (OUTLINE, Value::Number(n)) => outline = n != 0.,
+                (SCALABLE, Value::Number(n)) => scalable = n != 0.,
+                (FOUNDRY, Value::String(name)) if name != "unknown" => foundry = Some(name),
+                (FONTVERSION, Value::Number(n)) => revision = n as u32,
+                (VARIABLE, Value::Number(n)) => variable = n != 0.,
+                (COLOR, Value::Number(n)) => color = n != 0.,
+                (CHARSET, Value::CharSet(set)) => charset = Some(set),
+                _ => {}
+            }
+        }
+    }
+    let families = with_langs(families, langs);
+    let styles = with_langs(styles, style_langs);
+    let full_names = with_langs(full_names, full_name_langs);
+    Some(CachedFont {
+        file: file?,
+        index,
+        families,
+        styles,
+        full_names,
+        postscript_name,
+        weight,
+        width,
+        slant,
+        spacing,
+        outline,
+        scalable,
+        foundry,
+        variable,
+        color,
+        revision,
+        charset,
+    })
+}
+
+/// Pairs names with the languages listed for them in the same order.
+fn with_langs(names: Vec<String>, langs: Vec<String>) -> Vec<(String, Option<String>)> {
+    let mut langs = langs.into_iter();
+    names.into_iter().map(|name| (name, langs.next())).collect()
+}
+
+fn range(value: Value) -> Option<(f64, f64)> {
+    match value {
+        Value::Number(n) => Some((n, n)),
+        Value::Range(start, end) => Some((start, end)),
+        _ => None,
+    }
+}
+
+/// Parses the value at the specified offset of a value list node.
+fn parse_value(image: &Image, value: usize) -> Option<Value> {
+    let data = value + 8;
+    Some(match image.i32(value)? {
+        TYPE_INTEGER | TYPE_BOOL => Value::Number(image.i32(data)? as f64),
+        TYPE_DOUBLE => Value::Number(image.f64(data)?),
+        TYPE_STRING => Value::String(image.string(image.pointer(value, data)?)?),
+        TYPE_RANGE => {
+            let range = image.pointer(value, data)?;
+            Value::Range(image.f64(range)?, image.f64(range + 8)?)
+        }
+        TYPE_CHARSET => {
+            let set = image.pointer(value, data)?;
+            let count = image.i32(set + 4)?.max(0) as usize;
+            let leaves = image.offset(set, set + 8)?;
+            let numbers = image.offset(set, set + 16)?;
+            let mut pages = Vec::new();
+            for i in 0..count {
+                let number = u16::from_ne_bytes(image.bytes(numbers + i * 2)?);
+                let leaf = image.offset(leaves, leaves + i * 8)?;
+                let mut bits = [0u32; 8];
+                for (j, word) in bits.iter_mut().enumerate() {
+                    *word = u32::from_ne_bytes(image.bytes(leaf + j * 4)?);
+                }
+                pages.push((number, bits));
+            }
+            Value::CharSet(CharSet { pages })
+        }
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cache written by fontconfig 2.14 for a directory of four generated
+    /// fonts.
+    #[test]
+    #[cfg(all(target_pointer_width = "64", target_endian = "little"))]
+    fn parse_cache() {
+        let dir = parse(include_bytes!("../tests/fixtures/fc-cache-le64.cache-8")).unwrap();
+        assert_eq!(dir.dir, Path::new("/tmp/fcfix/fonts"));
+        assert!(dir.subdirs.is_empty());
+        let mut fonts: Vec<_> = dir.fonts.iter().collect();
+        fonts.sort_by_key(|font| font.file.clone());
+        let names: Vec<_> = fonts
+            .iter()
+            .map(|font| {
+                (
+                    font.file.file_name().unwrap().to_str().unwrap(),
+                    font.families[0].0.as_str(),
+                    font.styles[0].0.as_str(),
+                    font.spacing,
+                )
+            })
+            .collect();
+        assert_eq!(
+            names,
+            [
+                (
+                    "FixtureDual-Regular.ttf",
+                    "Fixture Dual",
+                    "Regular",
+                    Spacing::Dual
+                ),
+                (
+                    "FixtureMono-Regular.ttf",
+                    "Fixture Mono",
+                    "Regular",
+                    Spacing::Mono
+                ),
+                (
+                    "FixtureSans-Bold.ttf",
+                    "Fixture Sans",
+                    "Bold",
+                    Spacing::Proportional
+                ),
+                (
+                    "FixtureSans-Regular.ttf",
+                    "Fixture Sans",
+                    "Regular",
+                    Spacing::Proportional
+                ),
+            ]
+        );
+        let chars = |font: &CachedFont| font.charset.as_ref().unwrap().chars().collect::<Vec<_>>();
+        assert_eq!(
+            chars(fonts[0]),
+            (0x41..=0x5A).chain(0x3041..=0x3096).collect::<Vec<_>>()
+        );
+        assert!(fonts[1].charset.as_ref().unwrap().contains('0' as u32));
+        assert!(!fonts[1].charset.as_ref().unwrap().contains('a' as u32));
+        assert_eq!(fonts[2].weight, Some((200., 200.)));
+        assert_eq!(fonts[3].weight, Some((80., 80.)));
+        assert_eq!(fonts[3].families[0].1.as_deref(), Some("en"));
+        assert_eq!(fonts[3].foundry.as_deref(), Some("TEST"));
+        assert!(fonts.iter().all(|font| font.outline && font.scalable));
+        assert!(fonts.iter().all(|font| font.index == 0 && !font.variable));
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn value_list_cycle() {
+        // A pattern with a single file element whose value list node is
+        // followed by the specified next pointer.
+        let image = |next: i64| {
+            let mut data = vec![0u8; 64];
+            data[..4].copy_from_slice(&1i32.to_ne_bytes());
+            data[8..16].copy_from_slice(&16i64.to_ne_bytes());
+            data[16..20].copy_from_slice(&FILE.to_ne_bytes());
+            data[24..32].copy_from_slice(&(16i64 | 1).to_ne_bytes());
+            data[32..40].copy_from_slice(&next.to_ne_bytes());
+            data[40..44].copy_from_slice(&TYPE_STRING.to_ne_bytes());
+            data[48..56].copy_from_slice(&(24i64 | 1).to_ne_bytes());
+            data.extend_from_slice(b"Loop.ttf\0");
+            data
+        };
+        let dir = Path::new("/fonts");
+        let font = parse_pattern(&Image(&image(0)), 0, dir).unwrap();
+        assert_eq!(font.file, dir.join("Loop.ttf"));
+        // A node that links to itself.
+        assert!(parse_pattern(&Image(&image(1)), 0, dir).is_none());
+    }
+}
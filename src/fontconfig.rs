@@ -0,0 +1,526 @@
+//! Evaluation of fontconfig configuration rules.
+
+use super::builder::FontInfo;
+use super::substitute::{self, Binding, Compare, EditMode, EditValue, Rule};
+use super::util::glob::Glob;
+use fontconfig_parser::{
+    Alias, Constant, EditBinding, Expression, FontConfig, FontMatch, Match, MatchTarget, Property,
+    PropertyKind, SelectFont, TestCompare, TestQual, TestTarget, Value,
+};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use swash::Style;
+
+/// Font selection rules from `<selectfont>` elements.
+///
+/// As with fontconfig, a font is accepted if it matches any `<acceptfont>`
+/// rule and otherwise rejected if it matches any `<rejectfont>` rule.
+#[derive(Clone, Default)]
+pub struct SelectRules {
+    accepts: Vec<Selector>,
+    rejects: Vec<Selector>,
+}
+
+#[derive(Clone)]
+enum Selector {
+    Glob(Glob),
+    Pattern(Vec<Property>),
+}
+
+impl Selector {
+    fn new(rule: &FontMatch) -> Self {
+        match rule {
+            FontMatch::Glob(glob) => Self::Glob(Glob::separator_insensitive(glob)),
+            FontMatch::Pattern(props) => Self::Pattern(props.clone()),
+        }
+    }
+}
+
+impl SelectRules {
+    pub fn new(select_fonts: &[SelectFont]) -> Self {
+        let mut rules = Self::default();
+        for select in select_fonts {
+            rules
+                .accepts
+                .extend(select.accepts.iter().map(Selector::new));
+            rules
+                .rejects
+                .extend(select.rejects.iter().map(Selector::new));
+        }
+        rules
+    }
+
+    /// Returns true if the font should be added to the index. The path is
+    /// `None` for fonts loaded from memory.
+    pub fn accepts(&self, path: Option<&Path>, font: &FontInfo) -> bool {
+        if self.rejects.is_empty() {
+            return true;
+        }
+        let matches = |rule: &Selector| match rule {
+            Selector::Glob(glob) => path.is_some_and(|path| glob.matches(path)),
+            Selector::Pattern(props) => props.iter().all(|prop| prop_matches(prop, path, font)),
+        };
+        self.accepts.iter().any(matches) || !self.rejects.iter().any(matches)
+    }
+}
+
+/// Converts `<match target="pattern">` and `<alias>` elements to family
+/// substitution rules.
+///
+/// The order of matches relative to aliases is not known after parsing, so
+/// alias rules are applied last. This follows the usual configuration where
+/// the aliases that expand generic families come after the matches that add
+/// them.
+pub fn substitution_rules(fontconfig: &FontConfig) -> Vec<Rule> {
+    let bindings = alias_bindings(&fontconfig.config_files);
+    let mut rules = match_rules(&fontconfig.matches);
+    rules.extend(fontconfig.aliases.iter().map(|alias| {
+        let binding = bindings
+            .iter()
+            .find(|(other, _)| other == alias)
+            .map_or(Binding::Weak, |(_, binding)| *binding);
+        alias_rule(alias, binding)
+    }));
+    rules
+}
+
+/// Converts an alias to a rule that inserts the preferred families before
+/// the alias, the accepted families after it and the default families at
+/// the end of the list.
+fn alias_rule(alias: &Alias, binding: Binding) -> Rule {
+    let edits = [
+        (EditMode::Prepend, &alias.prefer),
+        (EditMode::Append, &alias.accept),
+        (EditMode::AppendLast, &alias.default),
+    ]
+    .iter()
+    .filter(|(_, families)| !families.is_empty())
+    .map(|(mode, families)| substitute::Edit {
+        mode: *mode,
+        binding,
+        values: families
+            .iter()
+            .map(|name| EditValue::Name(name.clone()))
+            .collect(),
+    })
+    .collect();
+    Rule {
+        tests: vec![substitute::Test::Family {
+            all: false,
+            compare: Compare::Eq,
+            name: alias.alias.clone(),
+        }],
+        edits,
+    }
+}
+
+/// Reads the bindings of the `<alias>` elements in the configuration files,
+/// which are not kept by the parser. Aliases are bound weakly by default.
+fn alias_bindings(files: &HashSet<PathBuf>) -> Vec<(Alias, Binding)> {
+    let mut bindings = Vec::new();
+    for path in files {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(_) => continue,
+        };
+        let options = roxmltree::ParsingOptions {
+            allow_dtd: true,
+            ..Default::default()
+        };
+        let doc = match roxmltree::Document::parse_with_options(&text, options) {
+            Ok(doc) => doc,
+            Err(_) => continue,
+        };
+        for node in doc.root_element().children() {
+            if node.tag_name().name() != "alias" {
+                continue;
+            }
+            let binding = match node.attribute("binding") {
+                Some("strong") => Binding::Strong,
+                Some("same") => Binding::Same,
+                _ => Binding::Weak,
+            };
+            if binding == Binding::Weak {
+                continue;
+            }
+            let mut alias = Alias::default();
+            for child in node.children() {
+                let families = child
+                    .children()
+                    .filter(|family| family.tag_name().name() == "family")
+                    .filter_map(|family| family.text().map(String::from));
+                match child.tag_name().name() {
+                    "family" => alias.alias = child.text().unwrap_or_default().into(),
+                    "prefer" => alias.prefer.extend(families),
+                    "accept" => alias.accept.extend(families),
+                    "default" => alias.default.extend(families),
+                    _ => {}
+                }
+            }
+            bindings.push((alias, binding));
+        }
+    }
+    bindings
+}
+
+/// Converts `<match target="pattern">` elements to family substitution
+/// rules. Edits of other properties are ignored and rules without any
+/// family edits are dropped.
+fn match_rules(matches: &[Match]) -> Vec<Rule> {
+    matches
+        .iter()
+        .filter(|m| m.target == MatchTarget::Pattern)
+        .filter_map(|m| {
+            let edits: Vec<_> = m
+                .edits
+                .iter()
+                .filter_map(|edit| {
+                    let expr = match &edit.value {
+                        Property::Family(expr) => expr,
+                        _ => return None,
+                    };
+                    let mut values = Vec::new();
+                    collect_values(expr, &mut values);
+                    let values = values
+                        .into_iter()
+                        .filter_map(|value| match value {
+                            Value::String(name) => Some(EditValue::Name(name.clone())),
+                            Value::Property(_, PropertyKind::Family) => Some(EditValue::Families),
+                            _ => None,
+                        })
+                        .collect();
+                    Some(substitute::Edit {
+                        mode: edit_mode(edit.mode),
+                        binding: match edit.binding {
+                            EditBinding::Strong => Binding::Strong,
+                            EditBinding::Weak => Binding::Weak,
+                            EditBinding::Same => Binding::Same,
+                        },
+                        values,
+                    })
+                })
+                .collect();
+            if edits.is_empty() {
+                return None;
+            }
+            let tests = m
+                .tests
+                .iter()
+                .map(|test| {
+                    let all = test.qual == TestQual::All;
+                    let compare = match test.compare {
+                        TestCompare::Eq => Compare::Eq,
+                        TestCompare::NotEq => Compare::NotEq,
+                        TestCompare::Contains => Compare::Contains,
+                        TestCompare::NotContains => Compare::NotContains,
+                        _ => return substitute::Test::Other { all },
+                    };
+                    if matches!(test.target, TestTarget::Font | TestTarget::Scan) {
+                        return substitute::Test::Other { all };
+                    }
+                    let string = |expr: &Expression| match expr {
+                        Expression::Simple(Value::String(s))
+                        | Expression::Simple(Value::LangSet(s)) => Some(s.clone()),
+                        _ => None,
+                    };
+                    match &test.value {
+                        Property::Family(expr) => match string(expr) {
+                            Some(name) => substitute::Test::Family { all, compare, name },
+                            None => substitute::Test::Other { all },
+                        },
+                        Property::Lang(expr) => match string(expr) {
+                            Some(lang) => substitute::Test::Lang { compare, lang },
+                            None => substitute::Test::Other { all },
+                        },
+                        _ => substitute::Test::Other { all },
+                    }
+                })
+                .collect();
+            Some(Rule { tests, edits })
+        })
+        .collect()
+}
+
+fn edit_mode(mode: fontconfig_parser::EditMode) -> EditMode {
+    use fontconfig_parser::EditMode as Mode;
+    match mode {
+        Mode::Assign => EditMode::Assign,
+        Mode::AssignReplace => EditMode::AssignReplace,
+        Mode::Prepend => EditMode::Prepend,
+        Mode::PrependFirst => EditMode::PrependFirst,
+        Mode::Append => EditMode::Append,
+        Mode::AppendLast => EditMode::AppendLast,
+        Mode::Delete => EditMode::Delete,
+        Mode::DeleteAll => EditMode::DeleteAll,
+    }
+}
+
+/// Returns true if the font has a value for the property that matches one
+/// of the values in the pattern element. Properties that are not known for
+/// indexed fonts never match.
+fn prop_matches(prop: &Property, path: Option<&Path>, font: &FontInfo) -> bool {
+    let kind = prop.kind();
+    let expr = match prop {
+        Property::Family(expr)
+        | Property::Weight(expr)
+        | Property::Slant(expr)
+        | Property::Width(expr)
+        | Property::File(expr)
+        | Property::Index(expr)
+        | Property::Scalable(expr)
+        | Property::Outline(expr) => expr,
+        _ => return false,
+    };
+    let mut values = Vec::new();
+    collect_values(expr, &mut values);
+    values.iter().any(|value| match (&kind, value) {
+        (PropertyKind::Family, Value::String(name)) => {
+            let name = normalize_family(name);
+            normalize_family(&font.name) == name
+                || font
+                    .aliases
+                    .iter()
+                    .chain(font.all_names())
+                    .any(|other| normalize_family(other) == name)
+        }
+        (PropertyKind::File, Value::String(file)) => {
+            path.is_some_and(|path| path == Path::new(file))
+        }
+        (PropertyKind::Index, Value::Int(index)) => *index == font.index,
+        (PropertyKind::Scalable, Value::Bool(yes)) => *yes == font.scalable,
+        (PropertyKind::Outline, Value::Bool(yes)) => *yes == font.outline,
+        (PropertyKind::Weight, _) | (PropertyKind::Slant, _) | (PropertyKind::Width, _) => {
+            let actual = match kind {
+                PropertyKind::Weight => fc_weight(font.weight.0),
+                PropertyKind::Slant => match font.style {
+                    Style::Normal => 0.,
+                    Style::Italic => 100.,
+                    Style::Oblique(_) => 110.,
+                },
+                _ => font.stretch.to_percentage() as f64,
+            };
+            match value {
+                Value::Int(value) => *value as f64 == actual,
+                Value::Double(value) => *value == actual,
+                Value::Constant(constant) => constant_value(*constant, &kind) == Some(actual),
+                Value::Range(start, end) => (*start as f64..=*end as f64).contains(&actual),
+                _ => false,
+            }
+        }
+        _ => false,
+    })
+}
+
+fn collect_values<'a>(expr: &'a Expression, values: &mut Vec<&'a Value>) {
+    match expr {
+        Expression::Simple(value) => values.push(value),
+        Expression::List(_, exprs) => {
+            for expr in exprs {
+                collect_values(expr, values);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn constant_value(constant: Constant, kind: &PropertyKind) -> Option<f64> {
+    constant.get_value(kind.clone()).map(|value| value as f64)
+}
+
+/// Normalizes a family name for comparison by ignoring case and spaces as
+/// fontconfig does.
+fn normalize_family(name: &str) -> String {
+    name.chars()
+        .filter(|c| *c != ' ')
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+/// Pairs of OpenType and fontconfig weights between which weights are
+/// linearly interpolated.
+const WEIGHTS: [(f64, f64); 12] = [
+    (100., 0.),
+    (200., 40.),
+    (300., 50.),
+    (350., 55.),
+    (380., 75.),
+    (400., 80.),
+    (500., 100.),
+    (600., 180.),
+    (700., 200.),
+    (800., 205.),
+    (900., 210.),
+    (1000., 215.),
+];
+
+/// Converts an OpenType weight to the fontconfig weight scale.
+fn fc_weight(weight: u16) -> f64 {
+    let weight = (weight as f64).clamp(100., 1000.);
+    for pair in WEIGHTS.windows(2) {
+        let ((ot0, fc0), (ot1, fc1)) = (pair[0], pair[1]);
+        if weight <= ot1 {
+            return fc0 + (weight - ot0) * (fc1 - fc0) / (ot1 - ot0);
+        }
+    }
+    215.
+}
+
+/// Converts a weight on the fontconfig scale to an OpenType weight.
+pub fn ot_weight(weight: f64) -> u16 {
+    let weight = weight.clamp(0., 215.);
+    for pair in WEIGHTS.windows(2) {
+        let ((ot0, fc0), (ot1, fc1)) = (pair[0], pair[1]);
+        if weight <= fc1 {
+            return (ot0 + (weight - fc0) * (ot1 - ot0) / (fc1 - fc0)).round() as u16;
+        }
+    }
+    1000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn select_rules(xml: &str) -> SelectRules {
+        let selects: Vec<_> = fontconfig_parser::parse_config_parts(xml)
+            .unwrap()
+            .into_iter()
+            .filter_map(|part| match part {
+                fontconfig_parser::ConfigPart::SelectFont(select) => Some(select),
+                _ => None,
+            })
+            .collect();
+        SelectRules::new(&selects)
+    }
+
+    #[test]
+    fn select_fonts() {
+        let rules = select_rules(
+            r#"<fontconfig><selectfont>
+                <acceptfont><glob>/fonts/keep/*</glob></acceptfont>
+                <rejectfont><glob>/fonts/*.pcf</glob></rejectfont>
+                <rejectfont><pattern>
+                    <patelt name="scalable"><bool>false</bool></patelt>
+                </pattern></rejectfont>
+            </selectfont></fontconfig>"#,
+        );
+        let outline = FontInfo {
+            name: "Test".into(),
+            outline: true,
+            scalable: true,
+            ..Default::default()
+        };
+        let bitmap = FontInfo {
+            name: "Test".into(),
+            ..Default::default()
+        };
+        let path = |path| Some(Path::new(path));
+        assert!(rules.accepts(path("/fonts/a.ttf"), &outline));
+        assert!(rules.accepts(None, &outline));
+        assert!(!rules.accepts(path("/fonts/a.otb"), &bitmap));
+        assert!(!rules.accepts(path("/fonts/x/a.pcf"), &outline));
+        assert!(rules.accepts(path("/fonts/keep/a.pcf"), &bitmap));
+        let rules = select_rules(
+            r#"<fontconfig><selectfont><rejectfont><pattern>
+                <patelt name="outline"><bool>true</bool></patelt>
+                <patelt name="family"><string>Test</string></patelt>
+            </pattern></rejectfont></selectfont></fontconfig>"#,
+        );
+        assert!(!rules.accepts(None, &outline));
+        assert!(rules.accepts(None, &bitmap));
+    }
+
+    #[test]
+    fn select_family_names() {
+        use crate::builder::Scanner;
+        use crate::util::test_font::TestFont;
+
+        let data = TestFont {
+            subfamily: "Regular".into(),
+            typographic_family: Some("Legacy".into()),
+            typographic_subfamily: Some("Light".into()),
+            weight: 300,
+            ..TestFont::new("Legacy Light")
+        }
+        .build();
+        let mut fonts = Vec::new();
+        Scanner::default().scan_data(&data, false, |font| fonts.push(font.clone()));
+        let font = &fonts[0];
+        // Both the typographic and legacy families match without localized
+        // names.
+        for (family, accepted) in [("Legacy", false), ("Legacy Light", false), ("Other", true)] {
+            let rules = select_rules(&format!(
+                r#"<fontconfig><selectfont><rejectfont><pattern>
+                    <patelt name="family"><string>{}</string></patelt>
+                </pattern></rejectfont></selectfont></fontconfig>"#,
+                family
+            ));
+            assert_eq!(rules.accepts(None, font), accepted, "{}", family);
+        }
+    }
+
+    #[test]
+    fn alias_substitution() {
+        let dir = crate::util::test_font::temp_dir("fontconfig-alias");
+        let path = dir.join("fonts.conf");
+        fs::write(
+            &path,
+            r#"<?xml version="1.0"?>
+            <!DOCTYPE fontconfig SYSTEM "urn:fontconfig:fonts.dtd">
+            <fontconfig>
+                <alias binding="same">
+                    <family>Arial</family>
+                    <accept><family>Liberation Sans</family></accept>
+                </alias>
+                <alias>
+                    <family>sans-serif</family>
+                    <prefer><family>DejaVu Sans</family></prefer>
+                    <default><family>Unifont</family></default>
+                </alias>
+                <match target="pattern">
+                    <test name="family"><string>Helvetica</string></test>
+                    <edit name="family" mode="append" binding="strong">
+                        <string>sans-serif</string>
+                    </edit>
+                </match>
+            </fontconfig>"#,
+        )
+        .unwrap();
+        let mut fontconfig = FontConfig::default();
+        fontconfig.merge_config(&path).unwrap();
+        let subs = substitute::Substitutions::new(substitution_rules(&fontconfig), None);
+        let _ = fs::remove_dir_all(&dir);
+        let names = |name| {
+            subs.apply(name, None)
+                .into_iter()
+                .map(|(name, binding)| (name.to_string(), binding))
+                .collect::<Vec<_>>()
+        };
+        let family = |name: &str, binding| (name.to_string(), binding);
+        assert_eq!(
+            names("arial"),
+            [
+                family("arial", Binding::Strong),
+                family("liberation sans", Binding::Strong)
+            ]
+        );
+        assert_eq!(
+            names("helvetica"),
+            [
+                family("helvetica", Binding::Strong),
+                family("dejavu sans", Binding::Weak),
+                family("sans-serif", Binding::Strong),
+                family("unifont", Binding::Weak)
+            ]
+        );
+    }
+
+    #[test]
+    fn fontconfig_weight() {
+        assert_eq!(fc_weight(400), 80.);
+        assert_eq!(fc_weight(700), 200.);
+        assert_eq!(ot_weight(80.), 400);
+        assert_eq!(ot_weight(fc_weight(650)), 650);
+    }
+}
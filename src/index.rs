@@ -4,6 +4,7 @@ use super::index_data::*;
 use super::types::*;
 use super::{
     fallback::Fallbacks,
+    substitute::{Binding, Substitutions},
     types::{FamilyId, GenericFamily},
 };
 #[cfg(feature = "emacs")]
@@ -19,11 +20,12 @@ use log::warn;
 #[cfg(feature = "emacs")]
 use std::collections::HashSet;
 use std::path::Path;
+use swash::text::Language;
 use swash::text::{Cjk, Script};
 use swash::Tag;
+use swash::{Attributes, CacheKey, FontRef, NormalizedCoord, Setting};
 #[cfg(feature = "emacs")]
-use swash::{text::Language, Stretch, Style, Weight};
-use swash::{Attributes, CacheKey};
+use swash::{Stretch, Style, Weight};
 
 /// Type alias for signatures to distinguish between inherent and
 /// requested attributes.
@@ -32,6 +34,8 @@ pub type RequestedAttributes = Attributes;
 #[derive(Default)]
 pub struct BaseIndex {
     pub family_map: FxHashMap<SmallString, FamilyId>,
+    pub postscript_map: FxHashMap<SmallString, FontId>,
+    pub full_name_map: FxHashMap<SmallString, FontId>,
     pub fonts: Vec<FontData>,
     pub sources: Vec<SourceData>,
 }
@@ -39,6 +43,10 @@ pub struct BaseIndex {
 pub struct StaticIndex {
     pub base: BaseIndex,
     pub families: Vec<FamilyData>,
+    /// Families for alternate names that only some fonts of a family have,
+    /// such as the legacy family name `Inter Light`. Their identifiers
+    /// follow those of `families`.
+    pub alias_families: Vec<FamilyData>,
     pub script_map: FxHashMap<Script, Fallbacks>,
     pub script_tag_map: FxHashMap<Tag, Vec<FamilyId>>,
     pub language_tag_map: FxHashMap<Tag, Vec<FamilyId>>,
@@ -47,7 +55,12 @@ pub struct StaticIndex {
     #[cfg(feature = "emacs")]
     pub emacs_script_map: FxHashMap<SmallString, Vec<FamilyId>>,
     pub cjk: [Fallbacks; 5],
-    pub generic: [Option<FamilyId>; 13],
+    pub generic: [Option<FamilyId>; GenericFamily::COUNT],
+    pub generic_chains: [Vec<FamilyId>; GenericFamily::COUNT],
+    pub substitutions: Substitutions,
+    /// Map from a block of characters to the fonts of `families` that
+    /// support a character in the block, in family order.
+    pub coverage_blocks: FxHashMap<u32, Vec<FontId>>,
 }
 
 impl Default for StaticIndex {
@@ -56,6 +69,7 @@ impl Default for StaticIndex {
         Self {
             base: BaseIndex::default(),
             families: Vec::new(),
+            alias_families: Vec::new(),
             script_map: Default::default(),
             script_tag_map: Default::default(),
             language_tag_map: Default::default(),
@@ -64,7 +78,10 @@ impl Default for StaticIndex {
             #[cfg(feature = "emacs")]
             emacs_script_map: Default::default(),
             cjk: [fallbacks; 5],
-            generic: [None; 13],
+            generic: [None; GenericFamily::COUNT],
+            generic_chains: Default::default(),
+            substitutions: Substitutions::default(),
+            coverage_blocks: Default::default(),
         }
     }
 }
@@ -207,6 +224,23 @@ impl StaticIndex {
         }
     }
 
+    /// Maps the blocks of characters supported by the fonts of each family
+    /// for coverage queries.
+    pub fn setup_coverage_blocks(&mut self) {
+        self.coverage_blocks.clear();
+        for family in &self.families {
+            for font in &family.fonts {
+                let data = match self.base.fonts.get(font.id.to_usize()) {
+                    Some(data) => data,
+                    None => continue,
+                };
+                for block in data.coverage.blocks() {
+                    self.coverage_blocks.entry(block).or_default().push(font.id);
+                }
+            }
+        }
+    }
+
     pub fn setup_default_generic(&mut self) {
         use super::system::*;
         use GenericFamily::*;
@@ -266,6 +300,38 @@ impl StaticIndex {
                 self.generic[Emoji as usize] = self.find_family(&["noto color emoji", "emoji one"]);
             }
         }
+        if self.generic[Emoji as usize].is_none() {
+            self.generic[Emoji as usize] = self.find_color_emoji_family();
+        }
+    }
+
+    /// Returns the first family with a color font that supports emoji.
+    fn find_color_emoji_family(&self) -> Option<FamilyId> {
+        const GRINNING_FACE: u32 = 0x1F600;
+        self.families
+            .iter()
+            .find(|family| {
+                family.fonts.iter().any(|font| {
+                    self.base.fonts.get(font.id.to_usize()).is_some_and(|data| {
+                        !data.color.is_empty() && data.coverage.contains(GRINNING_FACE)
+                    })
+                })
+            })
+            .map(|family| family.id)
+    }
+
+    /// Returns the ordered list of families configured for the generic
+    /// family. This is the single mapped family when no fallback chain
+    /// has been configured.
+    pub fn generic_chain(&self, generic: GenericFamily) -> &[FamilyId] {
+        let chain = &self.generic_chains[generic as usize];
+        if !chain.is_empty() {
+            return chain;
+        }
+        match &self.generic[generic as usize] {
+            Some(id) => core::slice::from_ref(id),
+            None => &[],
+        }
     }
 
     pub fn emoji_family(&self) -> Option<FamilyId> {
@@ -311,15 +377,16 @@ impl StaticIndex {
 
 impl StaticIndex {
     /// Returns a font entry that matches the specified family and
-    /// attributes.
+    /// attributes. Color formats in the attributes restrict the match to
+    /// fonts with any of them.
     pub fn query<'a>(
         &'a self,
         family: impl Into<FamilyKey<'a>>,
-        attributes: impl Into<Attributes>,
+        attributes: impl Into<QueryAttributes>,
     ) -> Option<FontEntry<'a>> {
         let family = self.family_by_key(family)?;
-        let attrs = attributes.into();
-        let font_id = family.data.query(attrs)?;
+        let QueryAttributes { attributes, color } = attributes.into();
+        let font_id = family.data.match_(attributes, None, None, color, None)?;
         let data = self.base.fonts.get(font_id.to_usize())?;
         Some(FontEntry {
             index: &self.base,
@@ -336,13 +403,17 @@ impl StaticIndex {
                       stretch: Option<Stretch>,
                       weight: Option<Weight>,
                       style: Option<Style>,
+                      spacing: Option<Spacing>,
+                      foundry: Option<&str>,
                       otf: Option<OpentypeSpec>| {
             let family = self.family_by_key(family);
             if family.is_none() {
                 return vec![];
             }
             let family = family.unwrap();
-            let fonts = family.data.list(stretch, weight, style, otf);
+            let fonts = family
+                .data
+                .list(stretch, weight, style, spacing, foundry, otf);
             let fonts = fonts
                 .iter()
                 .filter_map(|font_id| {
@@ -359,41 +430,45 @@ impl StaticIndex {
             return fonts;
         };
 
-        //TODO impl spacing
         let FontSpec {
             width,
             weight,
             slant,
             spacing,
+            foundry,
             otf,
             ..
         } = spec.clone();
 
-        if let Some(_) = spacing {
-            warn!("spacing is not yet supported");
-        }
         self.families_by_spec(spec)
             .iter()
             .map(|family_id| FamilyKey::from(*family_id))
-            .map(|key| filter(key, width, weight, slant, otf.clone()))
+            .map(|key| {
+                filter(
+                    key,
+                    width,
+                    weight,
+                    slant,
+                    spacing,
+                    foundry.as_deref(),
+                    otf.clone(),
+                )
+            })
             .flatten()
             .collect()
     }
 
     #[cfg(feature = "emacs")]
     pub fn match_<'a>(&'a self, spec: FontSpec) -> Option<FontEntry<'a>> {
-        //TODO impl spacing
         let FontSpec {
             width,
             weight,
             slant,
             spacing,
+            foundry,
             otf,
             ..
         } = spec.clone();
-        if let Some(_) = spacing {
-            warn!("spacing is not yet supported");
-        }
         let attrs = Attributes::new(
             width.unwrap_or(Stretch::NORMAL),
             weight.unwrap_or(Weight::NORMAL),
@@ -403,7 +478,9 @@ impl StaticIndex {
         let query = |family: FamilyId, attributes: Attributes, otf: Option<OpentypeSpec>| {
             let family = self.family_by_key(family)?;
             let attrs = attributes.into();
-            let font_id = family.data.match_(attrs, otf)?;
+            let font_id = family
+                .data
+                .match_(attrs, spacing, foundry.as_deref(), None, otf)?;
             let data = self.base.fonts.get(font_id.to_usize())?;
             Some(FontEntry {
                 index: &self.base,
@@ -417,14 +494,12 @@ impl StaticIndex {
             .find_map(|family| query(*family, attrs, otf.clone()))
     }
 
-    //TODO impl foundry, ref to fontconfig foundry implementation
     //TODO impl XLFD-style or fontconfig-style font name, more defails from Emacs info
     #[cfg(feature = "emacs")]
     pub fn families_by_spec<'a>(
         &'a self,
         FontSpec {
             family,
-            foundry,
             registry,
             name,
             script,
@@ -433,10 +508,6 @@ impl StaticIndex {
             ..
         }: FontSpec,
     ) -> Vec<FamilyId> {
-        if let Some(_) = foundry {
-            warn!("foundry is not yet supported");
-        }
-
         if let Some(_) = name {
             warn!("name is not yet supported");
         }
@@ -555,28 +626,217 @@ impl StaticIndex {
         match key.into() {
             FamilyKey::Id(id) => self.family_by_id(id),
             FamilyKey::Name(name) => self.family_by_name(name),
-            FamilyKey::Generic(generic) => {
-                self.family_by_id(self.generic.get(generic as usize).copied()??)
-            }
+            FamilyKey::Generic(generic) => self.family_by_id(self.generic_family(generic)?),
         }
     }
 
     /// Returns a font family entry for the specified name.
     pub fn family_by_name<'a>(&'a self, name: &str) -> Option<FamilyEntry<'a>> {
+        self.family_by_name_and_lang(name, self.substitutions.lang())
+    }
+
+    /// Returns a font family entry for the specified name. Substitution
+    /// rules for the specified language apply to generic names and to names
+    /// of families that are not in the index.
+    pub fn family_by_name_and_lang<'a>(
+        &'a self,
+        name: &str,
+        lang: Option<&str>,
+    ) -> Option<FamilyEntry<'a>> {
         let mut s = LowercaseString::new();
         let name = s.get(name)?;
-        let id = if let Some(generic) = GenericFamily::parse(name) {
-            self.generic.get(generic as usize).copied()??
+        let id = if let Some(id) = self.base.family_map.get(name) {
+            *id
+        } else if let Some(id) = self.substitute(name, lang) {
+            id
         } else {
-            *self.base.family_map.get(name)?
+            self.generic
+                .get(GenericFamily::parse(name)? as usize)
+                .copied()??
         };
 
         self.family_by_id(id)
     }
 
+    fn generic_family(&self, generic: GenericFamily) -> Option<FamilyId> {
+        let name = match generic {
+            GenericFamily::Serif => "serif",
+            GenericFamily::SansSerif => "sans-serif",
+            GenericFamily::Monospace => "monospace",
+            GenericFamily::Cursive => "cursive",
+            GenericFamily::Fantasy => "fantasy",
+            GenericFamily::SystemUI => "system-ui",
+            GenericFamily::Math => "math",
+            GenericFamily::Emoji => "emoji",
+            GenericFamily::FangSong => "fangsong",
+        };
+        self.substitute(name, self.substitutions.lang())
+            .or_else(|| self.generic.get(generic as usize).copied()?)
+    }
+
+    /// Applies substitution rules to the lowercase family name and returns
+    /// the best family in the index. Strongly bound families are preferred
+    /// in order, followed by weakly bound families that support the
+    /// language and then other weakly bound families. Names that no rule
+    /// tests for are not substituted.
+    fn substitute(&self, name: &str, lang: Option<&str>) -> Option<FamilyId> {
+        if self.substitutions.is_empty() || !self.substitutions.targets(name) {
+            return None;
+        }
+        let generic_request = GenericFamily::parse(name).is_some();
+        let lang_families = lang
+            .and_then(Language::parse)
+            .and_then(|lang| lang.to_opentype())
+            .and_then(|tag| self.language_tag_map.get(&tag));
+        let mut best = None;
+        for (position, (family, binding)) in
+            self.substitutions.apply(name, lang).into_iter().enumerate()
+        {
+            // Generic names in the list stand for the default family of the
+            // generic when a generic family was requested.
+            let id = match self.base.family_map.get(family.as_str()) {
+                Some(id) => *id,
+                None => match GenericFamily::parse(family.as_str())
+                    .filter(|_| generic_request)
+                    .and_then(|generic| *self.generic.get(generic as usize)?)
+                {
+                    Some(id) => id,
+                    None => continue,
+                },
+            };
+            let key = if binding == Binding::Weak {
+                let unsupported = lang_families.is_some_and(|families| !families.contains(&id));
+                (true, unsupported, position)
+            } else {
+                (false, false, position)
+            };
+            match best {
+                Some((best_key, _)) if best_key <= key => {}
+                _ => best = Some((key, id)),
+            }
+        }
+        best.map(|(_, id)| id)
+    }
+
+    /// Returns the font with the specified PostScript name, such as
+    /// `DejaVuSans-BoldOblique`. Names are compared case insensitively.
+    pub fn font_by_postscript_name<'a>(&'a self, name: &str) -> Option<FontEntry<'a>> {
+        let mut s = LowercaseString::new();
+        let id = *self.base.postscript_map.get(s.get(name)?)?;
+        self.font_by_id(id)
+    }
+
+    /// Returns the font with the specified full name, such as
+    /// `DejaVu Sans Bold Oblique`. Names are compared case insensitively and
+    /// localized names are included when the library was built with all
+    /// names.
+    pub fn font_by_full_name<'a>(&'a self, name: &str) -> Option<FontEntry<'a>> {
+        let mut s = LowercaseString::new();
+        let id = *self.base.full_name_map.get(s.get(name)?)?;
+        self.font_by_id(id)
+    }
+
+    /// Returns the fonts that support the specified character. Fonts that
+    /// were shadowed by other fonts are not included.
+    pub fn fonts_covering<'a>(&'a self, ch: char) -> Vec<FontEntry<'a>> {
+        self.families
+            .iter()
+            .flat_map(|family| {
+                family.fonts.iter().filter_map(move |font| {
+                    let data = self.base.fonts.get(font.id.to_usize())?;
+                    if !data.coverage.contains(ch as u32) {
+                        return None;
+                    }
+                    Some(FontEntry {
+                        index: &self.base,
+                        family,
+                        data,
+                    })
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the families with a font that supports every character in
+    /// the string. Control characters are ignored.
+    pub fn families_covering_str<'a>(&'a self, s: &str) -> Vec<FamilyEntry<'a>> {
+        let chars: Vec<_> = s.chars().filter(|ch| !ch.is_control()).collect();
+        self.families
+            .iter()
+            .filter(|family| {
+                family.fonts.iter().any(|font| {
+                    self.base.fonts.get(font.id.to_usize()).is_some_and(|data| {
+                        chars.iter().all(|ch| data.coverage.contains(*ch as u32))
+                    })
+                })
+            })
+            .map(|data| FamilyEntry {
+                index: &self.base,
+                data,
+            })
+            .collect()
+    }
+
+    /// Returns the font closest to the requested attributes that supports
+    /// all of the specified characters. Fonts are compared by stretch, then
+    /// style and then weight.
+    pub fn font_covering_all<'a>(
+        &'a self,
+        chars: &[char],
+        attributes: impl Into<RequestedAttributes>,
+    ) -> Option<FontEntry<'a>> {
+        let (stretch, weight, style) = attributes.into().parts();
+        let mut best = None;
+        let mut candidate = |id: FontId| {
+            let data = match self.base.fonts.get(id.to_usize()) {
+                Some(data) => data,
+                None => return,
+            };
+            if !chars.iter().all(|ch| data.coverage.contains(*ch as u32)) {
+                return;
+            }
+            let family = &self.families[data.family.to_usize()];
+            let font = match family.fonts.iter().find(|font| font.id == id) {
+                Some(font) => font,
+                None => return,
+            };
+            let distance = font.distance(stretch, weight, style);
+            match &best {
+                Some((best_distance, _)) if *best_distance <= distance => {}
+                _ => best = Some((distance, (family, data))),
+            }
+        };
+        // Only fonts with a character in the block of the first character
+        // can support all of them.
+        match chars.first() {
+            Some(ch) => {
+                let block = Coverage::block(*ch as u32);
+                for id in self.coverage_blocks.get(&block)? {
+                    candidate(*id);
+                }
+            }
+            None => {
+                for family in &self.families {
+                    for font in &family.fonts {
+                        candidate(font.id);
+                    }
+                }
+            }
+        }
+        let (_, (family, data)) = best?;
+        Some(FontEntry {
+            index: &self.base,
+            family,
+            data,
+        })
+    }
+
     /// Returns a font family entry for the specified identifier.
     pub fn family_by_id<'a>(&'a self, id: FamilyId) -> Option<FamilyEntry<'a>> {
-        let data = self.families.get(id.to_usize())?;
+        let data = self.families.get(id.to_usize()).or_else(|| {
+            let index = id.to_usize().checked_sub(self.families.len())?;
+            self.alias_families.get(index)
+        })?;
         Some(FamilyEntry {
             index: &self.base,
             data,
@@ -624,6 +884,19 @@ impl<'a> FamilyEntry<'a> {
             })
         })
     }
+
+    /// Returns an iterator over the fonts in the family that were shadowed
+    /// by a font with the same attributes.
+    pub fn shadowed_fonts(&'a self) -> impl Iterator<Item = FontEntry<'a>> + 'a {
+        self.data.shadowed.iter().filter_map(move |id| {
+            let data = self.index.fonts.get(id.to_usize())?;
+            Some(FontEntry {
+                index: self.index,
+                family: self.data,
+                data,
+            })
+        })
+    }
 }
 
 /// Font entry in a library.
@@ -676,15 +949,99 @@ impl<'a> FontEntry<'a> {
         self.data.attributes
     }
 
+    /// Returns the variation axes of a variable font. This is empty for
+    /// named instances.
+    pub fn axes(&self) -> &'a [AxisRange] {
+        &self.data.axes
+    }
+
+    /// Returns the subfamily name, such as "Condensed SemiBold" for a named
+    /// instance.
+    pub fn subfamily_name(&self) -> &'a str {
+        self.data.subfamily.as_str()
+    }
+
+    /// Returns the index of the named instance in a variable font.
+    pub fn instance(&self) -> Option<u16> {
+        self.data.instance
+    }
+
+    /// Returns the normalized coordinates of a named instance.
+    pub fn coords(&self) -> &'a [NormalizedCoord] {
+        &self.data.coords
+    }
+
+    /// Returns the variation settings that instance a variable font for
+    /// the requested attributes. Values are limited to the axis ranges.
+    pub fn variation_settings(&self, attrs: RequestedAttributes) -> Vec<Setting<f32>> {
+        variation_settings(&self.data.axes, attrs)
+    }
+
+    /// Returns the normalized variation coordinates that instance a
+    /// variable font for the requested attributes. This loads the font data
+    /// and returns the coordinates of the instance for named instances.
+    pub fn normalized_coords(&self, attrs: RequestedAttributes) -> Vec<NormalizedCoord> {
+        if self.data.axes.is_empty() {
+            return self.data.coords.clone();
+        }
+        let data = match self.source().data.get() {
+            Some(data) => data,
+            None => return Vec::new(),
+        };
+        match FontRef::from_offset(&data, self.data.offset) {
+            Some(font) => font
+                .variations()
+                .normalized_coords(self.variation_settings(attrs))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns the font revision as a 16.16 fixed point number.
+    pub fn revision(&self) -> u32 {
+        self.data.revision
+    }
+
+    /// Returns the spacing of the glyphs in the font.
+    pub fn spacing(&self) -> Spacing {
+        self.data.spacing
+    }
+
+    /// Returns the foundry of the font from the vendor identifier in the
+    /// `OS/2` table or the manufacturer name. This is empty when unknown.
+    pub fn foundry(&self) -> &'a str {
+        self.data.foundry.as_str()
+    }
+
+    /// Returns the color glyph formats supported by the font. This is empty
+    /// for fonts without color glyphs.
+    pub fn color_formats(&self) -> ColorFormats {
+        self.data.color
+    }
+
+    /// Returns true if the font maps the character to a glyph.
+    pub fn has_char(&self, ch: char) -> bool {
+        self.data.coverage.contains(ch as u32)
+    }
+
+    /// Returns true if the font was shadowed by another font in the family
+    /// with the same attributes.
+    pub fn is_shadowed(&self) -> bool {
+        self.family.shadowed.contains(&self.data.id)
+    }
+
     pub fn cache_key(&self) -> CacheKey {
         self.data.key
     }
 
+    /// Returns the font identifier, the attributes of the font as instanced
+    /// for the requested attributes and the requested attributes.
     pub fn selector(
         &self,
         attrs: RequestedAttributes,
     ) -> (FontId, Attributes, RequestedAttributes) {
-        (self.data.id, self.data.attributes, attrs)
+        let instanced = instanced_attributes(&self.data.axes, self.data.attributes, attrs);
+        (self.data.id, instanced, attrs)
     }
 }
 
@@ -703,10 +1060,12 @@ impl<'a> SourceEntry<'a> {
 
     /// Returns the path of the source, if it is represented by a file.
     pub fn path(&self) -> Option<&Path> {
-        match &self.data.kind {
-            SourceKind::Memory(..) => None,
-            SourceKind::File(data) => Some(&data.path),
-        }
+        self.data.path()
+    }
+
+    /// Returns the layer that the source was added to.
+    pub fn layer(&self) -> SourceLayer {
+        self.data.layer
     }
 }
 
@@ -740,4 +1099,138 @@ mod tests {
             Err(String::from("two plus two does not equal four"))
         }
     }
+
+    #[test]
+    fn coverage_queries() {
+        use crate::util::test_font::{self, TestFont};
+        use crate::FontLibrary;
+
+        test_font::isolate();
+        let mut cyrillic = TestFont::new("Cyrillic");
+        cyrillic.chars.extend(('а'..='я').map(|ch| (ch, 600)));
+        let greek = TestFont {
+            chars: ('α'..='ω').map(|ch| (ch, 600)).collect(),
+            ..TestFont::new("Greek")
+        };
+        // A duplicate of the Latin font that is shadowed by the first one.
+        let mut shadowed = TestFont::new("Latin");
+        shadowed.chars.push(('א', 600));
+        let library = FontLibrary::builder()
+            .add_data(TestFont::new("Latin").build())
+            .add_data(cyrillic.build())
+            .add_data(greek.build())
+            .add_data(shadowed.build())
+            .build();
+        let index = library.index();
+        let fonts = |ch| {
+            let mut names: Vec<_> = index
+                .fonts_covering(ch)
+                .iter()
+                .map(|font| font.family_name().to_string())
+                .collect();
+            names.sort_unstable();
+            names
+        };
+        assert_eq!(fonts('A'), ["Cyrillic", "Latin"]);
+        assert_eq!(fonts('ж'), ["Cyrillic"]);
+        assert!(fonts('א').is_empty());
+        assert!(fonts('中').is_empty());
+        let families = |s| {
+            let mut names: Vec<_> = index
+                .families_covering_str(s)
+                .iter()
+                .map(|family| family.name().to_string())
+                .collect();
+            names.sort_unstable();
+            names
+        };
+        assert_eq!(families("Aж"), ["Cyrillic"]);
+        assert_eq!(families("A\n"), ["Cyrillic", "Latin"]);
+        assert_eq!(families("αβ"), ["Greek"]);
+        assert!(families("αA").is_empty());
+        assert!(families("中").is_empty());
+        let cyrillic = index.query("Cyrillic", ()).unwrap();
+        assert!(cyrillic.has_char('ж') && cyrillic.has_char('A'));
+        assert!(!cyrillic.has_char('中'));
+        assert!(!index.query("Latin", ()).unwrap().has_char('ж'));
+    }
+
+    #[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
+    #[test]
+    fn substituted_names() {
+        use super::*;
+        use crate::util::test_font::{self, TestFont};
+        use crate::{fontconfig, FontLibrary};
+        use std::fs;
+        use std::sync::Arc;
+
+        test_font::isolate();
+        let dir = test_font::temp_dir("index-substitution");
+        let path = dir.join("fonts.conf");
+        // Aliases along with the catch-all rule of 49-sansserif.conf, which
+        // appends sans-serif to every request for another family.
+        fs::write(
+            &path,
+            r#"<?xml version="1.0"?>
+            <!DOCTYPE fontconfig SYSTEM "urn:fontconfig:fonts.dtd">
+            <fontconfig>
+                <alias binding="same">
+                    <family>Arial</family>
+                    <accept><family>Liberation Sans</family></accept>
+                </alias>
+                <alias>
+                    <family>Liberation Sans</family>
+                    <prefer><family>DejaVu Sans</family></prefer>
+                </alias>
+                <alias>
+                    <family>sans-serif</family>
+                    <prefer><family>DejaVu Sans</family></prefer>
+                </alias>
+                <match target="pattern">
+                    <test qual="all" name="family" compare="not_eq">
+                        <string>sans-serif</string>
+                    </test>
+                    <test qual="all" name="family" compare="not_eq">
+                        <string>serif</string>
+                    </test>
+                    <test qual="all" name="family" compare="not_eq">
+                        <string>monospace</string>
+                    </test>
+                    <edit name="family" mode="append_last">
+                        <string>sans-serif</string>
+                    </edit>
+                </match>
+            </fontconfig>"#,
+        )
+        .unwrap();
+        let mut config = fontconfig_parser::FontConfig::default();
+        config.merge_config(&path).unwrap();
+        let rules = fontconfig::substitution_rules(&config);
+        let _ = fs::remove_dir_all(&dir);
+        let mut builder = FontLibrary::builder();
+        for family in ["DejaVu Sans", "Liberation Sans"] {
+            builder.add_data(TestFont::new(family).build());
+        }
+        let library = builder.build();
+        let mut index = library.index();
+        drop(library);
+        Arc::get_mut(&mut index).unwrap().substitutions = Substitutions::new(rules, None);
+        let family = |name| {
+            index
+                .family_by_name(name)
+                .map(|family| family.name().to_string())
+        };
+        // Families in the index are found before any substitution.
+        assert_eq!(
+            family("Liberation Sans").as_deref(),
+            Some("Liberation Sans")
+        );
+        assert_eq!(family("Arial").as_deref(), Some("Liberation Sans"));
+        assert_eq!(family("sans-serif").as_deref(), Some("DejaVu Sans"));
+        // Catch-all rules do not resolve unknown families to a generic.
+        assert_eq!(family("Unknown"), None);
+        assert!(index
+            .family_by_name_and_lang("Unknown", Some("ja"))
+            .is_none());
+    }
 }
@@ -2,20 +2,28 @@
 
 use super::{
     shared_data::{SharedData, WeakSharedData},
-    types::{FamilyId, FontId, SourceId},
+    types::{AxisRange, ColorFormats, FamilyId, FontId, SourceId, SourceLayer, Spacing},
 };
 use crate::types::OpentypeSpec;
 use crate::util::string::SmallString;
-use std::path::PathBuf;
-use std::sync::RwLock;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
 use std::time::SystemTime;
-use swash::{Attributes, CacheKey, Stretch, Style, Tag, Weight};
+use swash::{
+    Attributes, CacheKey, NormalizedCoord, ObliqueAngle, Setting, Stretch, Style, Tag, Weight,
+};
 
 #[derive(Clone)]
 pub struct FamilyData {
     pub id: FamilyId,
     pub name: SmallString,
     pub fonts: Vec<FamilyFontData>,
+    /// Fonts with the same attributes as a font in `fonts` that were not
+    /// selected by the duplicate policy.
+    pub shadowed: Vec<FontId>,
+    /// Highest layer of the fonts in the family. Fonts from lower layers are
+    /// shadowed.
+    pub layer: SourceLayer,
     pub has_stretch: bool,
 }
 
@@ -26,16 +34,169 @@ pub struct FamilyFontData {
     pub weight: Weight,
     pub style: Style,
     pub writing_systems: Vec<(Tag, Tag, Vec<Tag>)>,
+    /// Variation axes of a variable font.
+    pub axes: Vec<AxisRange>,
+    pub spacing: Spacing,
+    pub foundry: SmallString,
+    pub color: ColorFormats,
+}
+
+const WGHT: Tag = swash::tag_from_bytes(b"wght");
+const WDTH: Tag = swash::tag_from_bytes(b"wdth");
+const ITAL: Tag = swash::tag_from_bytes(b"ital");
+const SLNT: Tag = swash::tag_from_bytes(b"slnt");
+
+fn find_axis(axes: &[AxisRange], tag: Tag) -> Option<&AxisRange> {
+    axes.iter().find(|axis| axis.tag == tag)
+}
+
+impl FamilyFontData {
+    /// Returns the range of weights covered by the font.
+    pub fn weight_range(&self) -> (Weight, Weight) {
+        match find_axis(&self.axes, WGHT) {
+            Some(axis) => (Weight(axis.min as u16), Weight(axis.max as u16)),
+            None => (self.weight, self.weight),
+        }
+    }
+
+    /// Returns true if the font covers the specified stretch.
+    pub fn covers_stretch(&self, stretch: Stretch) -> bool {
+        match find_axis(&self.axes, WDTH) {
+            Some(axis) => axis.contains(stretch.to_percentage()),
+            None => self.stretch == stretch,
+        }
+    }
+
+    /// Returns how far the font is from the specified attributes as the
+    /// differences in stretch, style and weight, in order of importance.
+    /// Variable fonts have no difference within their ranges.
+    pub fn distance(&self, stretch: Stretch, weight: Weight, style: Style) -> (u16, bool, u16) {
+        let stretch = if self.covers_stretch(stretch) {
+            0
+        } else {
+            (self.stretch.raw() as i32 - stretch.raw() as i32).unsigned_abs() as u16
+        };
+        let (min, max) = self.weight_range();
+        let weight = if weight < min {
+            min.0 - weight.0
+        } else if weight > max {
+            weight.0 - max.0
+        } else {
+            0
+        };
+        (stretch, !self.supports_style(style), weight)
+    }
+
+    /// Returns true if the font is from the specified foundry, ignoring case.
+    pub fn has_foundry(&self, foundry: &str) -> bool {
+        self.foundry.as_str().eq_ignore_ascii_case(foundry.trim())
+    }
+
+    /// Returns true if the font has the specified style or can be varied
+    /// to it. Oblique angles are not distinguished.
+    pub fn supports_style(&self, style: Style) -> bool {
+        let axis = |tag| find_axis(&self.axes, tag);
+        match style {
+            Style::Normal => {
+                self.style == Style::Normal
+                    || [ITAL, SLNT]
+                        .iter()
+                        .any(|tag| axis(*tag).is_some_and(|axis| axis.contains(0.)))
+            }
+            Style::Italic => {
+                self.style == Style::Italic || axis(ITAL).is_some_and(|axis| axis.contains(1.))
+            }
+            Style::Oblique(_) => {
+                matches!(self.style, Style::Oblique(_))
+                    || axis(SLNT).is_some_and(|axis| axis.min < 0.)
+            }
+        }
+    }
+}
+
+/// Returns the variation settings that select the specified attributes
+/// within the axes of a variable font.
+pub fn variation_settings(axes: &[AxisRange], attributes: Attributes) -> Vec<Setting<f32>> {
+    let (stretch, weight, style) = attributes.parts();
+    let has_ital = find_axis(axes, ITAL).is_some();
+    axes.iter()
+        .filter_map(|axis| {
+            let value = match axis.tag {
+                WGHT => weight.0 as f32,
+                WDTH => stretch.to_percentage(),
+                ITAL => (style == Style::Italic) as u8 as f32,
+                // Positive slant angles lean backwards.
+                SLNT => match style {
+                    Style::Oblique(angle) => -angle.to_degrees(),
+                    Style::Italic if !has_ital => -14.,
+                    _ => 0.,
+                },
+                _ => return None,
+            };
+            Some(Setting {
+                tag: axis.tag,
+                value: axis.clamp(value),
+            })
+        })
+        .collect()
+}
+
+/// Returns the attributes of the instance of a variable font that
+/// [`variation_settings`] selects for the requested attributes. Attributes
+/// of fonts without axes are returned unchanged.
+pub fn instanced_attributes(
+    axes: &[AxisRange],
+    attributes: Attributes,
+    requested: Attributes,
+) -> Attributes {
+    if axes.is_empty() {
+        return attributes;
+    }
+    let settings = variation_settings(axes, requested);
+    let values = axes.iter().map(|axis| {
+        settings
+            .iter()
+            .find(|setting| setting.tag == axis.tag)
+            .map_or(axis.default, |setting| setting.value)
+    });
+    instance_attributes(axes, values, attributes)
+}
+
+/// Returns the attributes of a named instance with the specified axis
+/// values, starting from the attributes of its variable font.
+pub fn instance_attributes(
+    axes: &[AxisRange],
+    values: impl Iterator<Item = f32>,
+    attributes: Attributes,
+) -> Attributes {
+    let (mut stretch, mut weight, mut style) = attributes.parts();
+    for (axis, value) in axes.iter().zip(values) {
+        match axis.tag {
+            WGHT => weight = Weight(value.round() as u16),
+            WDTH => stretch = Stretch::from_percentage(value),
+            ITAL if value >= 0.5 => style = Style::Italic,
+            SLNT if value < 0. && style != Style::Italic => {
+                style = Style::Oblique(ObliqueAngle::from_degrees(-value))
+            }
+            _ => {}
+        }
+    }
+    Attributes::new(stretch, weight, style)
 }
 
 impl FamilyData {
     pub fn contains(&self, stretch: Stretch, weight: Weight, style: Style) -> bool {
+        self.find(stretch, weight, style).is_some()
+    }
+
+    /// Returns the font with exactly the specified attributes.
+    pub fn find(&self, stretch: Stretch, weight: Weight, style: Style) -> Option<FontId> {
         for font in &self.fonts {
             if font.stretch == stretch && font.weight == weight && font.style == style {
-                return true;
+                return Some(font.id);
             }
         }
-        false
+        None
     }
 
     #[cfg(feature = "emacs")]
@@ -44,10 +205,24 @@ impl FamilyData {
         stretch: Option<Stretch>,
         weight: Option<Weight>,
         style: Option<Style>,
+        spacing: Option<Spacing>,
+        foundry: Option<&str>,
         otf: Option<OpentypeSpec>,
     ) -> Vec<FontId> {
         self.fonts
             .iter()
+            .filter(|font| {
+                if spacing.is_some() && !font.spacing.satisfies(spacing.unwrap()) {
+                    return false;
+                }
+                true
+            })
+            .filter(|font| {
+                if foundry.is_some() && !font.has_foundry(foundry.unwrap()) {
+                    return false;
+                }
+                true
+            })
             .filter(|font| {
                 if let Some((script_tag, language_tag, included_tags, excluded_tags)) = &otf {
                     if font
@@ -77,20 +252,23 @@ impl FamilyData {
                 true
             })
             .filter(|font| {
-                if stretch.is_some() && stretch.unwrap() != font.stretch {
+                if stretch.is_some() && !font.covers_stretch(stretch.unwrap()) {
                     return false;
                 }
                 true
             })
             .filter(|font| {
-                if style.is_some() && style.unwrap() != font.style {
+                if style.is_some() && !font.supports_style(style.unwrap()) {
                     return false;
                 }
                 true
             })
             .filter(|font| {
-                if weight.is_some() && weight.unwrap() != font.weight {
-                    return false;
+                if let Some(weight) = weight {
+                    let (min, max) = font.weight_range();
+                    if weight < min || weight > max {
+                        return false;
+                    }
                 }
                 true
             })
@@ -98,9 +276,27 @@ impl FamilyData {
             .collect()
     }
 
-    /// Returns the font that most closely matches the specified attributes.
-    pub fn match_(&self, attributes: Attributes, otf: Option<OpentypeSpec>) -> Option<FontId> {
+    /// Returns the font that most closely matches the specified attributes
+    /// among the fonts with the requested spacing, foundry and any of the
+    /// requested color formats.
+    pub fn match_(
+        &self,
+        attributes: Attributes,
+        spacing: Option<Spacing>,
+        foundry: Option<&str>,
+        color: Option<ColorFormats>,
+        otf: Option<OpentypeSpec>,
+    ) -> Option<FontId> {
         let filtered_fonts = self.fonts.iter().filter(|font| {
+            if spacing.is_some() && !font.spacing.satisfies(spacing.unwrap()) {
+                return false;
+            }
+            if foundry.is_some() && !font.has_foundry(foundry.unwrap()) {
+                return false;
+            }
+            if color.is_some() && !font.color.intersects(color.unwrap()) {
+                return false;
+            }
             if let Some((script_tag, language_tag, included_tags, excluded_tags)) = &otf {
                 if font
                     .writing_systems
@@ -135,7 +331,12 @@ impl FamilyData {
 
         let mut matching_stretch = Stretch::NORMAL;
         if self.has_stretch {
-            if let Some(val) = filtered_fonts.clone().find_map(|font| {
+            if filtered_fonts
+                .clone()
+                .any(|font| font.covers_stretch(stretch))
+            {
+                matching_stretch = stretch;
+            } else if let Some(val) = filtered_fonts.clone().find_map(|font| {
                 let val = font.stretch;
                 let font_stretch = if stretch <= Stretch::NORMAL {
                     if val > Stretch::NORMAL {
@@ -163,61 +364,68 @@ impl FamilyData {
             }
         }
 
-        let filtered_fonts = filtered_fonts.filter(|f| f.stretch == matching_stretch);
+        let filtered_fonts = filtered_fonts.filter(|f| f.covers_stretch(matching_stretch));
 
+        let oblique = Style::Oblique(Default::default());
         let mut matching_style;
         match style {
             Style::Normal => {
                 matching_style = Style::Italic;
                 for font in filtered_fonts.clone() {
-                    let val = font.style;
-                    match val {
-                        Style::Normal => {
-                            matching_style = style;
-                            break;
-                        }
-                        Style::Oblique(_) => {
-                            matching_style = val;
-                        }
-                        _ => {}
+                    if font.supports_style(style) {
+                        matching_style = style;
+                        break;
+                    }
+                    if font.supports_style(oblique) {
+                        matching_style = oblique;
                     }
                 }
             }
             Style::Oblique(_) => {
                 matching_style = Style::Normal;
                 for font in filtered_fonts.clone() {
-                    let val = font.style;
-                    match val {
-                        Style::Oblique(_) => {
-                            matching_style = style;
-                            break;
-                        }
-                        Style::Italic => {
-                            matching_style = val;
-                        }
-                        _ => {}
+                    if font.supports_style(style) {
+                        matching_style = style;
+                        break;
+                    }
+                    if font.supports_style(Style::Italic) {
+                        matching_style = Style::Italic;
                     }
                 }
             }
             Style::Italic => {
                 matching_style = Style::Normal;
                 for font in filtered_fonts.clone() {
-                    let val = font.style;
-                    match val {
-                        Style::Italic => {
-                            matching_style = style;
-                            break;
-                        }
-                        Style::Oblique(_) => {
-                            matching_style = val;
-                        }
-                        _ => {}
+                    if font.supports_style(style) {
+                        matching_style = style;
+                        break;
+                    }
+                    if font.supports_style(oblique) {
+                        matching_style = oblique;
                     }
                 }
             }
         }
 
-        let filtered_fonts = filtered_fonts.filter(|f| f.style == matching_style);
+        let filtered_fonts = filtered_fonts.filter(|f| f.supports_style(matching_style));
+
+        // Fonts that cover the desired weight match exactly, preferring
+        // static fonts over variable fonts.
+        if let Some(font) = filtered_fonts
+            .clone()
+            .filter(|f| {
+                let (min, max) = f.weight_range();
+                min <= weight && weight <= max
+            })
+            .min_by_key(|f| !f.axes.is_empty())
+        {
+            return Some(font.id);
+        }
+        // Otherwise, the closest weight of each font is the start of its
+        // range when it is above the desired weight and the end of its range
+        // when it is below.
+        let min = |f: &&FamilyFontData| f.weight_range().0;
+        let max = |f: &&FamilyFontData| f.weight_range().1;
 
         // If the desired weight is inclusively between 400 and 500
         if weight >= Weight(400) && weight <= Weight(500) {
@@ -225,45 +433,45 @@ impl FamilyData {
             // in ascending order until 500 is hit and checked
             for font in filtered_fonts
                 .clone()
-                .filter(|f| f.weight >= weight && f.weight <= Weight(500))
+                .filter(|f| min(f) >= weight && min(f) <= Weight(500))
             {
                 return Some(font.id);
             }
             // followed by weights less than the target weight in descending
             // order
-            for font in filtered_fonts.clone().rev().filter(|f| f.weight < weight) {
+            for font in filtered_fonts.clone().rev().filter(|f| max(f) < weight) {
                 return Some(font.id);
             }
             // followed by weights greater than 500, until a match is found
             return filtered_fonts
-                .filter(|f| f.weight > Weight(500))
+                .filter(|f| min(f) > Weight(500))
                 .map(|f| f.id)
                 .next();
         // If the desired weight is less than 400
         } else if weight < Weight(400) {
             // weights less than or equal to the desired weight are checked in
             // descending order
-            for font in filtered_fonts.clone().rev().filter(|f| f.weight <= weight) {
+            for font in filtered_fonts.clone().rev().filter(|f| max(f) <= weight) {
                 return Some(font.id);
             }
             // followed by weights above the desired weight in ascending order
             // until a match is found
             return filtered_fonts
-                .filter(|f| f.weight > weight)
+                .filter(|f| min(f) > weight)
                 .map(|f| f.id)
                 .next();
         // If the desired weight is greater than 500
         } else {
             // weights greater than or equal to the desired weight are checked
             // in ascending order
-            for font in filtered_fonts.clone().filter(|f| f.weight >= weight) {
+            for font in filtered_fonts.clone().filter(|f| min(f) >= weight) {
                 return Some(font.id);
             }
             // followed by weights below the desired weight in descending order
             // until a match is found
             return filtered_fonts
                 .rev()
-                .filter(|f| f.weight < weight)
+                .filter(|f| max(f) < weight)
                 .map(|f| f.id)
                 .next();
         }
@@ -271,11 +479,69 @@ impl FamilyData {
 
     /// Returns the font that most closely matches the specified attributes.
     pub fn query(&self, attributes: Attributes) -> Option<FontId> {
-        self.match_(attributes, None)
+        self.match_(attributes, None, None, None, None)
+    }
+}
+
+/// Set of characters supported by a font, stored as sorted and disjoint
+/// inclusive ranges.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct Coverage {
+    ranges: Vec<(u32, u32)>,
+}
+
+impl Coverage {
+    /// Creates a coverage set from characters in any order.
+    pub fn from_chars(chars: impl IntoIterator<Item = u32>) -> Self {
+        let mut chars: Vec<_> = chars.into_iter().collect();
+        chars.sort_unstable();
+        let mut ranges: Vec<(u32, u32)> = Vec::new();
+        for ch in chars {
+            match ranges.last_mut() {
+                Some(last) if ch <= last.1 + 1 => last.1 = last.1.max(ch),
+                _ => ranges.push((ch, ch)),
+            }
+        }
+        Self { ranges }
+    }
+
+    /// Creates a coverage set from sorted and disjoint ranges.
+    pub fn from_ranges(ranges: Vec<(u32, u32)>) -> Self {
+        Self { ranges }
+    }
+
+    /// Returns the inclusive ranges of characters in the set.
+    pub fn ranges(&self) -> &[(u32, u32)] {
+        &self.ranges
+    }
+
+    /// Returns true if the character is in the set.
+    pub fn contains(&self, ch: u32) -> bool {
+        let i = self.ranges.partition_point(|range| range.1 < ch);
+        self.ranges.get(i).is_some_and(|range| range.0 <= ch)
+    }
+
+    /// Returns the block of 256 characters that contains a character.
+    pub fn block(ch: u32) -> u32 {
+        ch >> 8
+    }
+
+    /// Returns the blocks with at least one character in the set, in
+    /// ascending order.
+    pub fn blocks(&self) -> impl Iterator<Item = u32> + '_ {
+        let mut last = None;
+        self.ranges
+            .iter()
+            .flat_map(|(start, end)| Self::block(*start)..=Self::block(*end))
+            .filter(move |block| {
+                let new = last != Some(*block);
+                last = Some(*block);
+                new
+            })
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub struct FontData {
     pub id: FontId,
     pub family: FamilyId,
@@ -283,6 +549,19 @@ pub struct FontData {
     pub index: u32,
     pub offset: u32,
     pub attributes: Attributes,
+    /// Font revision as a 16.16 fixed point number.
+    pub revision: u32,
+    pub axes: Vec<AxisRange>,
+    /// Subfamily name of the font or of its named instance.
+    pub subfamily: SmallString,
+    /// Index of the named instance in a variable font.
+    pub instance: Option<u16>,
+    /// Normalized coordinates of the named instance.
+    pub coords: Vec<NormalizedCoord>,
+    pub spacing: Spacing,
+    pub foundry: SmallString,
+    pub coverage: Arc<Coverage>,
+    pub color: ColorFormats,
     pub key: CacheKey,
 }
 
@@ -337,6 +616,7 @@ pub enum FileDataStatus {
 
 pub struct SourceData {
     pub id: SourceId,
+    pub layer: SourceLayer,
     pub kind: SourceKind,
 }
 
@@ -347,9 +627,38 @@ impl SourceData {
             SourceKind::Memory(data) => Some(data.clone()),
         }
     }
+
+    /// Returns the path of the source, if it is represented by a file.
+    pub fn path(&self) -> Option<&Path> {
+        match &self.kind {
+            SourceKind::Memory(..) => None,
+            SourceKind::File(data) => Some(&data.path),
+        }
+    }
 }
 
 pub enum SourceKind {
     Memory(SharedData),
     File(FileData),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Coverage;
+
+    #[test]
+    fn coverage() {
+        let coverage = Coverage::from_chars([0x41, 0x20, 0x42, 0x21, 0x4E00, 0x41]);
+        assert_eq!(
+            coverage.ranges(),
+            &[(0x20, 0x21), (0x41, 0x42), (0x4E00, 0x4E00)]
+        );
+        assert!(coverage.contains(0x20) && coverage.contains(0x42) && coverage.contains(0x4E00));
+        assert!(
+            !coverage.contains(0x1F) && !coverage.contains(0x43) && !coverage.contains(0x10FFFF)
+        );
+        assert_eq!(coverage.blocks().collect::<Vec<_>>(), [0, 0x4E]);
+        let coverage = Coverage::from_ranges(vec![(0x20, 0x7E), (0xA0, 0x17F), (0x1E00, 0x1E01)]);
+        assert_eq!(coverage.blocks().collect::<Vec<_>>(), [0, 1, 0x1E]);
+    }
+}
@@ -5,31 +5,46 @@ pub mod prelude;
 mod util;
 
 mod builder;
+mod cache;
 pub mod context;
 #[cfg(feature = "emacs")]
 pub mod emacs;
 mod fallback;
 mod family;
+#[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
+mod fc_cache;
+#[cfg(all(unix, not(any(target_os = "macos", target_os = "android"))))]
+mod fontconfig;
 mod index;
 mod index_data;
 mod library;
+mod report;
 mod shared_data;
+mod substitute;
 mod system;
 mod types;
+#[cfg(all(feature = "watch", target_os = "linux"))]
+mod watch;
+#[cfg(feature = "woff")]
+mod woff;
 
 pub(crate) mod internal {
     pub use super::context::{FontContext, FontGroupId};
 }
 use std::sync::{Arc, OnceLock};
 
-pub use builder::{FontLibraryBuilder, MmapHint};
+pub use builder::{DuplicatePolicy, FontLibraryBuilder, MmapHint};
 pub use context::{FontCache, FontContext};
 pub use family::{parse_families, FamilyList};
 pub use index::{FamilyEntry, FontEntry, SourceEntry, StaticIndex as FontIndex};
 pub use index_data::SourceKind;
 pub use library::FontLibrary;
+pub use report::{ScanOutcome, ScanReport, SourceReport};
 pub use shared_data::SharedData;
-pub use types::{FamilyId, FamilyKey, FontId, FontKey, GenericFamily, SourceId};
+pub use types::{
+    AxisRange, ColorFormats, FamilyId, FamilyKey, FontId, FontKey, GenericFamily, QueryAttributes,
+    SourceId, SourceLayer, Spacing,
+};
 
 use swash::{iter::*, CacheKey, *};
 
@@ -40,6 +55,7 @@ pub struct Font {
     offset: u32,
     attributes: Attributes,
     key: CacheKey,
+    coords: Vec<NormalizedCoord>,
 }
 
 impl Font {
@@ -52,6 +68,7 @@ impl Font {
                 offset,
                 attributes,
                 key,
+                coords: Vec::new(),
             });
         }
         None
@@ -73,6 +90,13 @@ impl Font {
         self.attributes
     }
 
+    /// Returns the normalized variation coordinates that instance a
+    /// variable font for the requested attributes or a named instance. This
+    /// is empty for the default instance.
+    pub fn coords(&self) -> &[NormalizedCoord] {
+        &self.coords
+    }
+
     /// Returns an iterator over the localized strings for the font.
     pub fn localized_strings(&self) -> LocalizedStrings {
         self.as_ref().localized_strings()
@@ -161,10 +185,15 @@ impl<'a> From<&'a Font> for FontRef<'a> {
 
 static FONT_LIBRARY: OnceLock<FontLibrary> = OnceLock::new();
 
+impl FontLibrary {
+    /// Returns the shared library of system and user fonts.
+    pub fn global() -> &'static FontLibrary {
+        FONT_LIBRARY.get_or_init(|| FontLibrary::default())
+    }
+}
+
 impl FontIndex {
     pub fn global() -> Arc<FontIndex> {
-        let library = FONT_LIBRARY.get_or_init(|| FontLibrary::default());
-
-        library.inner.index.read().unwrap().clone()
+        FontLibrary::global().index()
     }
 }
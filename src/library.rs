@@ -1,6 +1,12 @@
 use super::builder::{FontLibraryBuilder, MmapHint};
 use super::index::StaticIndex;
-use std::sync::{Arc, RwLock};
+#[cfg(all(feature = "watch", target_os = "linux"))]
+use crate::util::fxhash::FxHashSet;
+#[cfg(all(feature = "watch", target_os = "linux"))]
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex, RwLock};
 
 /// Indexed collection of fonts used during layout for font selection and
 /// fallback.
@@ -15,12 +21,87 @@ impl FontLibrary {
         FontLibraryBuilder::default()
     }
 
-    pub(super) fn new(index: StaticIndex) -> Self {
-        Self {
+    pub(super) fn new(index: StaticIndex, config: FontLibraryBuilder) -> Self {
+        #[cfg(all(feature = "watch", target_os = "linux"))]
+        let watch = config.watching();
+        let library = Self {
             inner: Arc::new(Inner {
                 index: RwLock::new(Arc::new(index)),
+                generation: AtomicU64::new(0),
+                config: Mutex::new(config),
+                subscribers: Mutex::new(Vec::new()),
+                #[cfg(all(feature = "watch", target_os = "linux"))]
+                watch: Mutex::new(None),
             }),
+        };
+        #[cfg(all(feature = "watch", target_os = "linux"))]
+        if watch {
+            match super::watch::spawn(&library) {
+                Ok(handle) => *library.inner.watch.lock().unwrap() = Some(handle),
+                Err(err) => log::warn!("failed to watch font directories: {}", err),
+            }
         }
+        library
+    }
+
+    /// Returns the current index.
+    pub fn index(&self) -> Arc<StaticIndex> {
+        self.inner.index.read().unwrap().clone()
+    }
+
+    /// Returns the generation of the current index. This is incremented
+    /// each time the library is refreshed.
+    pub fn generation(&self) -> u64 {
+        self.inner.generation.load(Ordering::Acquire)
+    }
+
+    /// Rescans all directories, files and data that were added to the
+    /// builder and replaces the current index.
+    ///
+    /// Font caches and contexts created from this library pick up the new
+    /// index when they are updated.
+    pub fn refresh(&self) {
+        let mut config = self.inner.config.lock().unwrap();
+        let index = config.rebuild();
+        self.replace(index);
+    }
+
+    /// Rescans the specified directories, reusing the last scan of all
+    /// other sources, and replaces the current index.
+    #[cfg(all(feature = "watch", target_os = "linux"))]
+    pub(super) fn refresh_dirs(&self, dirs: &FxHashSet<PathBuf>) {
+        let mut config = self.inner.config.lock().unwrap();
+        let index = config.rebuild_dirs(dirs);
+        self.replace(index);
+    }
+
+    /// Replaces the current index and notifies subscribers.
+    fn replace(&self, index: StaticIndex) {
+        let index = Arc::new(index);
+        let generation = {
+            let mut current = self.inner.index.write().unwrap();
+            *current = index;
+            self.inner.generation.fetch_add(1, Ordering::Release) + 1
+        };
+        self.inner
+            .subscribers
+            .lock()
+            .unwrap()
+            .retain(|sender| sender.send(generation).is_ok());
+    }
+
+    /// Returns a receiver that is sent the new generation each time the
+    /// library is refreshed.
+    pub fn subscribe(&self) -> Receiver<u64> {
+        let (sender, receiver) = channel();
+        self.inner.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Returns the current index along with its generation.
+    pub(super) fn current(&self) -> (Arc<StaticIndex>, u64) {
+        let index = self.inner.index.read().unwrap();
+        (index.clone(), self.inner.generation.load(Ordering::Acquire))
     }
 }
 
@@ -39,4 +120,44 @@ impl Default for FontLibrary {
 
 pub struct Inner {
     pub index: RwLock<Arc<StaticIndex>>,
+    pub generation: AtomicU64,
+    pub(super) config: Mutex<FontLibraryBuilder>,
+    subscribers: Mutex<Vec<Sender<u64>>>,
+    /// Directory watcher that is stopped when the library is dropped.
+    #[cfg(all(feature = "watch", target_os = "linux"))]
+    pub(super) watch: Mutex<Option<super::watch::Handle>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_font::{self, TestFont};
+    use std::fs;
+
+    #[test]
+    fn refresh() {
+        test_font::isolate();
+        let dir = test_font::temp_dir("library-refresh");
+        fs::write(dir.join("Alpha.ttf"), TestFont::new("Alpha").build()).unwrap();
+        let library = FontLibrary::builder().add_dir(&dir).build();
+        let receiver = library.subscribe();
+        assert_eq!(library.generation(), 0);
+        assert!(library.index().family_by_name("Beta").is_none());
+        fs::write(dir.join("Beta.ttf"), TestFont::new("Beta").build()).unwrap();
+        library.refresh();
+        assert_eq!(library.generation(), 1);
+        assert_eq!(receiver.try_recv(), Ok(1));
+        let index = library.index();
+        assert!(index.family_by_name("Alpha").is_some());
+        assert!(index.family_by_name("Beta").is_some());
+        // Subscribers that are dropped are no longer notified.
+        drop(receiver);
+        let receiver = library.subscribe();
+        fs::remove_file(dir.join("Alpha.ttf")).unwrap();
+        library.refresh();
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), [2]);
+        assert!(library.index().family_by_name("Alpha").is_none());
+        assert_eq!(library.inner.subscribers.lock().unwrap().len(), 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
 }
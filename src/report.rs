@@ -0,0 +1,84 @@
+//! Diagnostics collected while scanning font sources.
+
+use super::types::FontId;
+use std::path::{Path, PathBuf};
+
+/// Outcome of scanning a font or source.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ScanOutcome {
+    /// The font at the index was added to the library.
+    Added { index: u32, id: FontId },
+    /// The font at the index has the same family and attributes as a font
+    /// that was added before it and was kept as a shadowed font according
+    /// to the duplicate policy.
+    Duplicate {
+        index: u32,
+        id: FontId,
+        existing: FontId,
+    },
+    /// The font at the index belongs to a family that is provided by a
+    /// source in a higher layer and was kept as a shadowed font.
+    Overridden { index: u32, id: FontId },
+    /// The font at the index was rejected by a fontconfig `<selectfont>`
+    /// rule.
+    Rejected { index: u32 },
+    /// The font at the index could not be parsed.
+    InvalidFont { index: u32 },
+    /// The font at the index does not have a family name.
+    MissingFamilyName { index: u32 },
+    /// The source is not in a supported font format.
+    UnsupportedFormat,
+    /// The file could not be read.
+    Unreadable(String),
+}
+
+impl ScanOutcome {
+    /// Returns true if a font was added to the library.
+    pub fn is_added(&self) -> bool {
+        matches!(self, Self::Added { .. })
+    }
+}
+
+/// Outcomes for a single font file or in-memory font.
+#[derive(Clone, Debug)]
+pub struct SourceReport {
+    /// Path of the file or `None` for fonts added from memory.
+    pub path: Option<PathBuf>,
+    /// Outcomes for the source and each font it contains.
+    pub outcomes: Vec<ScanOutcome>,
+}
+
+/// Diagnostics for all sources scanned while building a library.
+#[derive(Clone, Default, Debug)]
+pub struct ScanReport {
+    pub sources: Vec<SourceReport>,
+}
+
+impl ScanReport {
+    /// Records an outcome for the most recently entered source.
+    pub(crate) fn add(&mut self, outcome: ScanOutcome) {
+        if let Some(source) = self.sources.last_mut() {
+            source.outcomes.push(outcome);
+        }
+    }
+
+    /// Returns the report for the specified file.
+    pub fn source(&self, path: impl AsRef<Path>) -> Option<&SourceReport> {
+        let path = path.as_ref();
+        self.sources
+            .iter()
+            .find(|source| source.path.as_deref() == Some(path))
+    }
+
+    /// Returns an iterator over all outcomes that did not add a font to the
+    /// library.
+    pub fn issues(&self) -> impl Iterator<Item = (&SourceReport, &ScanOutcome)> + '_ {
+        self.sources.iter().flat_map(|source| {
+            source
+                .outcomes
+                .iter()
+                .filter(|outcome| !outcome.is_added())
+                .map(move |outcome| (source, outcome))
+        })
+    }
+}
@@ -53,13 +53,21 @@ impl WeakSharedData {
 
 impl SharedData {
     /// Creates shared data from the specified bytes.
+    ///
+    /// WOFF and WOFF2 data is decompressed when the `woff` feature is
+    /// enabled.
     pub fn new(data: Vec<u8>) -> Self {
+        #[cfg(feature = "woff")]
+        let data = super::woff::decode(&data).unwrap_or(data);
         Self {
             inner: Arc::new(Inner::Memory(data)),
         }
     }
 
     /// Creates shared data from the specified path.
+    ///
+    /// WOFF and WOFF2 files are decompressed into memory when the `woff`
+    /// feature is enabled.
     pub fn from_file(
         path: impl AsRef<Path>,
         mmap: bool,
@@ -74,15 +82,22 @@ impl SharedData {
                 return Err(io::Error::from(io::ErrorKind::InvalidData));
             }
         }
-        let inner = Arc::new(if mmap {
+        let inner = if mmap {
             let file = std::fs::File::open(path)?;
             let map = unsafe { memmap2::Mmap::map(&file)? };
             Inner::Mapped(map)
         } else {
             let data = std::fs::read(path)?;
             Inner::Memory(data)
-        });
-        Ok(Self { inner })
+        };
+        #[cfg(feature = "woff")]
+        let inner = match super::woff::decode(inner.data()) {
+            Some(data) => Inner::Memory(data),
+            None => inner,
+        };
+        Ok(Self {
+            inner: Arc::new(inner),
+        })
     }
 
     /// Creates a new weak reference to the data.
@@ -0,0 +1,380 @@
+//! Family substitution rules applied when resolving family names.
+//!
+//! Rules follow the model of fontconfig `<match target="pattern">`
+//! elements: each rule tests the requested family list and language and,
+//! when all tests pass, edits the family list. The edited list is then
+//! resolved against the index by preferring strongly bound families over
+//! weakly bound ones, which are in turn ordered by support for the
+//! requested language.
+
+use crate::util::fxhash::FxHashMap;
+use crate::util::string::SmallString;
+use std::sync::RwLock;
+
+/// Maximum number of substituted family lists that are kept.
+const MAX_RESOLVED_ENTRIES: usize = 256;
+
+/// Strength of a family in a substituted family list.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Binding {
+    /// Preferred in order over all weakly bound families.
+    Strong,
+    /// Preferred in order among families with the same language support.
+    Weak,
+    /// Same binding as the family that matched the rule.
+    Same,
+}
+
+/// Position at which an edit inserts or removes families.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum EditMode {
+    Assign,
+    AssignReplace,
+    Prepend,
+    PrependFirst,
+    Append,
+    AppendLast,
+    Delete,
+    DeleteAll,
+}
+
+/// Comparison performed by a test.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Compare {
+    Eq,
+    NotEq,
+    Contains,
+    NotContains,
+}
+
+/// Condition on the requested pattern.
+#[derive(Clone, Debug)]
+pub enum Test {
+    /// Compares the requested families with a name. When `all` is false, the
+    /// test passes if any family matches.
+    Family {
+        all: bool,
+        compare: Compare,
+        name: String,
+    },
+    /// Compares the requested language with a language.
+    Lang { compare: Compare, lang: String },
+    /// Test on a property that is not part of a family request. Passes only
+    /// when `all` is true, as with missing properties in fontconfig.
+    Other { all: bool },
+}
+
+/// Value inserted by an edit.
+#[derive(Clone, Debug)]
+pub enum EditValue {
+    Name(String),
+    /// The families that are currently requested.
+    Families,
+}
+
+/// Change to the requested family list.
+#[derive(Clone, Debug)]
+pub struct Edit {
+    pub mode: EditMode,
+    pub binding: Binding,
+    pub values: Vec<EditValue>,
+}
+
+/// Substitution rule with tests and edits to apply when they pass.
+#[derive(Clone, Debug, Default)]
+pub struct Rule {
+    pub tests: Vec<Test>,
+    pub edits: Vec<Edit>,
+}
+
+/// Ordered set of substitution rules.
+#[derive(Default)]
+pub struct Substitutions {
+    rules: Vec<Rule>,
+    lang: Option<String>,
+    resolved: RwLock<FxHashMap<ResolvedKey, Vec<(SmallString, Binding)>>>,
+}
+
+/// Requested family name and language of a substituted family list.
+type ResolvedKey = (SmallString, Option<SmallString>);
+
+impl Substitutions {
+    /// Creates substitutions with the specified rules and default language.
+    pub fn new(rules: Vec<Rule>, lang: Option<String>) -> Self {
+        Self {
+            rules,
+            lang,
+            resolved: Default::default(),
+        }
+    }
+
+    /// Returns true if there are no rules.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Returns the default language of family requests.
+    pub fn lang(&self) -> Option<&str> {
+        self.lang.as_deref()
+    }
+
+    /// Returns true if a rule tests for the lowercase family name. Rules
+    /// that apply to any family, such as those that append a default
+    /// generic family to every request, do not count.
+    pub fn targets(&self, name: &str) -> bool {
+        let name = normalize(name);
+        self.rules.iter().any(|rule| {
+            rule.tests.iter().any(|test| match test {
+                Test::Family {
+                    compare: Compare::Eq,
+                    name: expected,
+                    ..
+                } => normalize(expected) == name,
+                Test::Family {
+                    compare: Compare::Contains,
+                    name: expected,
+                    ..
+                } => name.contains(&normalize(expected)),
+                _ => false,
+            })
+        })
+    }
+
+    /// Applies all rules to a request for the specified lowercase family
+    /// name and language and returns the resulting families in order.
+    pub fn apply(&self, name: &str, lang: Option<&str>) -> Vec<(SmallString, Binding)> {
+        let key = (SmallString::new(name), lang.map(SmallString::new));
+        if let Some(families) = self.resolved.read().unwrap().get(&key) {
+            return families.clone();
+        }
+        let mut families = vec![(name.to_string(), Binding::Strong)];
+        for rule in &self.rules {
+            let mut position = None;
+            let passed = rule.tests.iter().all(|test| match test {
+                Test::Family { all, compare, name } => {
+                    let name = normalize(name);
+                    let mut matched = families
+                        .iter()
+                        .map(|(family, _)| compare_names(*compare, &normalize(family), &name));
+                    if *all {
+                        matched.all(|yes| yes)
+                    } else if let Some(i) = matched.position(|yes| yes) {
+                        position = Some(i);
+                        true
+                    } else {
+                        false
+                    }
+                }
+                Test::Lang {
+                    compare,
+                    lang: expected,
+                } => match lang {
+                    Some(lang) => compare_langs(*compare, lang, expected),
+                    None => false,
+                },
+                Test::Other { all } => *all,
+            });
+            if !passed {
+                continue;
+            }
+            for edit in &rule.edits {
+                position = apply_edit(&mut families, edit, position);
+            }
+        }
+        let families: Vec<_> = families
+            .into_iter()
+            .map(|(family, binding)| (SmallString::new(&family.to_lowercase()), binding))
+            .collect();
+        let mut resolved = self.resolved.write().unwrap();
+        if resolved.len() >= MAX_RESOLVED_ENTRIES {
+            resolved.clear();
+        }
+        resolved.insert(key, families.clone());
+        families
+    }
+}
+
+/// Applies an edit to the family list. Returns the new position of the
+/// family that matched the rule, if any.
+fn apply_edit(
+    families: &mut Vec<(String, Binding)>,
+    edit: &Edit,
+    position: Option<usize>,
+) -> Option<usize> {
+    let binding = match edit.binding {
+        Binding::Same => position.map_or(Binding::Strong, |i| families[i].1),
+        binding => binding,
+    };
+    let mut values = Vec::new();
+    for value in &edit.values {
+        match value {
+            EditValue::Name(name) => values.push((name.clone(), binding)),
+            EditValue::Families => {
+                values.extend(families.iter().map(|(family, _)| (family.clone(), binding)))
+            }
+        }
+    }
+    let count = values.len();
+    match (edit.mode, position) {
+        (EditMode::Assign, Some(i)) => {
+            families.splice(i..=i, values);
+            None
+        }
+        (EditMode::Assign, None) | (EditMode::AssignReplace, _) => {
+            *families = values;
+            None
+        }
+        (EditMode::Prepend, Some(i)) => {
+            families.splice(i..i, values);
+            Some(i + count)
+        }
+        (EditMode::Prepend, None) | (EditMode::PrependFirst, _) => {
+            families.splice(0..0, values);
+            position.map(|i| i + count)
+        }
+        (EditMode::Append, Some(i)) => {
+            families.splice(i + 1..i + 1, values);
+            position
+        }
+        (EditMode::Append, None) | (EditMode::AppendLast, _) => {
+            families.extend(values);
+            position
+        }
+        (EditMode::Delete, Some(i)) => {
+            families.remove(i);
+            None
+        }
+        (EditMode::Delete, None) | (EditMode::DeleteAll, _) => {
+            families.clear();
+            None
+        }
+    }
+}
+
+/// Normalizes a family name by ignoring case and blanks.
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+fn compare_names(compare: Compare, name: &str, expected: &str) -> bool {
+    match compare {
+        Compare::Eq => name == expected,
+        Compare::NotEq => name != expected,
+        Compare::Contains => name.contains(expected),
+        Compare::NotContains => !name.contains(expected),
+    }
+}
+
+/// Compares languages where `contains` only requires the same primary
+/// language, so that a request for `ja-JP` contains `ja`.
+fn compare_langs(compare: Compare, lang: &str, expected: &str) -> bool {
+    let normalize = |lang: &str| lang.to_ascii_lowercase().replace('_', "-");
+    let (lang, expected) = (normalize(lang), normalize(expected));
+    let primary = |lang: &str| lang.split('-').next().unwrap_or("").to_string();
+    match compare {
+        Compare::Eq => lang == expected,
+        Compare::NotEq => lang != expected,
+        Compare::Contains => primary(&lang) == primary(&expected),
+        Compare::NotContains => primary(&lang) != primary(&expected),
+    }
+}
+
+/// Returns the language of the current locale from the environment.
+pub fn locale_lang() -> Option<String> {
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .and_then(|value| {
+            let lang = value.split(['.', '@']).next()?.replace('_', "-");
+            if lang.is_empty() || lang == "C" || lang == "POSIX" {
+                None
+            } else {
+                Some(lang)
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(test: &str, mode: EditMode, binding: Binding, names: &[&str]) -> Rule {
+        Rule {
+            tests: vec![Test::Family {
+                all: false,
+                compare: Compare::Eq,
+                name: test.into(),
+            }],
+            edits: vec![Edit {
+                mode,
+                binding,
+                values: names
+                    .iter()
+                    .map(|name| EditValue::Name(name.to_string()))
+                    .collect(),
+            }],
+        }
+    }
+
+    #[test]
+    fn substitute() {
+        let subs = Substitutions::new(
+            vec![
+                rule(
+                    "Helvetica",
+                    EditMode::Append,
+                    Binding::Same,
+                    &["Nimbus Sans"],
+                ),
+                rule("monospace", EditMode::Prepend, Binding::Weak, &["A", "B"]),
+                Rule {
+                    tests: vec![
+                        Test::Family {
+                            all: false,
+                            compare: Compare::Eq,
+                            name: "monospace".into(),
+                        },
+                        Test::Lang {
+                            compare: Compare::Contains,
+                            lang: "ja".into(),
+                        },
+                    ],
+                    edits: vec![Edit {
+                        mode: EditMode::PrependFirst,
+                        binding: Binding::Strong,
+                        values: vec![EditValue::Name("Noto Sans Mono CJK JP".into())],
+                    }],
+                },
+            ],
+            None,
+        );
+        let names = |name, lang| {
+            subs.apply(name, lang)
+                .into_iter()
+                .map(|(name, binding)| (name.to_string(), binding))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names("helvetica", None),
+            [
+                ("helvetica".to_string(), Binding::Strong),
+                ("nimbus sans".to_string(), Binding::Strong)
+            ]
+        );
+        assert_eq!(
+            names("monospace", None)[0],
+            ("a".to_string(), Binding::Weak)
+        );
+        assert_eq!(names("monospace", None)[2].0, "monospace");
+        assert_eq!(
+            names("monospace", Some("ja_JP"))[0].0,
+            "noto sans mono cjk jp"
+        );
+        assert!(subs.targets("helvetica"));
+        assert!(!subs.targets("nimbus sans"));
+    }
+}
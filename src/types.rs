@@ -1,6 +1,6 @@
 //! Basic types for interacting with a font library.
 
-use swash::{Attributes, Tag};
+use swash::{Attributes, Stretch, Style, Tag, Weight};
 
 use crate::util::string::LowercaseString;
 
@@ -34,6 +34,196 @@ impl SourceId {
     }
 }
 
+/// Layer of a font source. Families and fonts in a higher layer override
+/// those in lower layers.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Debug)]
+pub enum SourceLayer {
+    /// Fonts installed for all users.
+    System,
+    /// Fonts installed for the current user.
+    User,
+    /// Fonts provided by the application.
+    #[default]
+    App,
+}
+
+/// Spacing of the glyphs in a font, with the values used by fontconfig.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default, Debug)]
+#[repr(u8)]
+pub enum Spacing {
+    /// Glyphs have varying advances.
+    #[default]
+    Proportional = 0,
+    /// Glyphs have one of two advances where one is twice the other, as in
+    /// monospaced CJK fonts.
+    Dual = 90,
+    /// All glyphs have the same advance.
+    Mono = 100,
+    /// All glyphs have the same advance and fit in a cell, as in bitmap
+    /// terminal fonts.
+    Charcell = 110,
+}
+
+impl Spacing {
+    /// Returns the spacing for a fontconfig spacing value.
+    pub fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            0 => Self::Proportional,
+            90 => Self::Dual,
+            100 => Self::Mono,
+            110 => Self::Charcell,
+            _ => return None,
+        })
+    }
+
+    /// Returns true if a font with this spacing satisfies a request for
+    /// the specified spacing. Character cell fonts are also monospaced.
+    pub fn satisfies(self, requested: Spacing) -> bool {
+        self == requested || (self == Self::Charcell && requested == Self::Mono)
+    }
+}
+
+/// Set of color glyph formats supported by a font.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default, Debug)]
+pub struct ColorFormats(u8);
+
+impl ColorFormats {
+    /// Layered glyphs from version 0 of the `COLR` table.
+    pub const COLR_V0: Self = Self(1);
+    /// Gradients and transforms from version 1 of the `COLR` table.
+    pub const COLR_V1: Self = Self(2);
+    /// Color bitmaps in the `CBDT` and `CBLC` tables.
+    pub const CBDT: Self = Self(4);
+    /// Color bitmaps in the `sbix` table.
+    pub const SBIX: Self = Self(8);
+    /// SVG documents in the `SVG ` table.
+    pub const SVG: Self = Self(16);
+    /// All color formats.
+    pub const ALL: Self = Self(31);
+
+    /// Creates a set from its bit representation. Unknown bits are ignored.
+    pub fn from_bits(bits: u8) -> Self {
+        Self(bits & Self::ALL.0)
+    }
+
+    /// Returns the bit representation of the set.
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Returns true if the font has no color glyphs.
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns true if all formats in `other` are in the set.
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns true if any format in `other` is in the set.
+    pub fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl core::ops::BitOr for ColorFormats {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl core::ops::BitOrAssign for ColorFormats {
+    fn bitor_assign(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
+/// Attributes for a font query along with filters on the matching fonts.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default, Debug)]
+pub struct QueryAttributes {
+    /// Requested stretch, weight and style.
+    pub attributes: Attributes,
+    /// Color glyph formats of which a matching font must support at least
+    /// one.
+    pub color: Option<ColorFormats>,
+}
+
+impl QueryAttributes {
+    /// Creates query attributes without filters.
+    pub fn new(attributes: impl Into<Attributes>) -> Self {
+        Self {
+            attributes: attributes.into(),
+            color: None,
+        }
+    }
+
+    /// Restricts the query to fonts with any of the specified color formats.
+    pub fn color(mut self, formats: ColorFormats) -> Self {
+        self.color = Some(formats);
+        self
+    }
+}
+
+impl From<Attributes> for QueryAttributes {
+    fn from(attributes: Attributes) -> Self {
+        Self::new(attributes)
+    }
+}
+
+impl From<Stretch> for QueryAttributes {
+    fn from(stretch: Stretch) -> Self {
+        Self::new(stretch)
+    }
+}
+
+impl From<Weight> for QueryAttributes {
+    fn from(weight: Weight) -> Self {
+        Self::new(weight)
+    }
+}
+
+impl From<Style> for QueryAttributes {
+    fn from(style: Style) -> Self {
+        Self::new(style)
+    }
+}
+
+impl From<()> for QueryAttributes {
+    fn from(_: ()) -> Self {
+        Self::default()
+    }
+}
+
+impl From<(Stretch, Weight, Style)> for QueryAttributes {
+    fn from(parts: (Stretch, Weight, Style)) -> Self {
+        Self::new(parts)
+    }
+}
+
+/// Range of a variation axis in a variable font.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct AxisRange {
+    pub tag: Tag,
+    pub min: f32,
+    pub default: f32,
+    pub max: f32,
+}
+
+impl AxisRange {
+    /// Returns true if the value is within the range of the axis.
+    pub fn contains(&self, value: f32) -> bool {
+        self.min <= value && value <= self.max
+    }
+
+    /// Returns the value limited to the range of the axis.
+    pub fn clamp(&self, value: f32) -> f32 {
+        value.max(self.min).min(self.max)
+    }
+}
+
 /// Describes a generic font family.
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 #[repr(u8)]
@@ -50,6 +240,9 @@ pub enum GenericFamily {
 }
 
 impl GenericFamily {
+    /// Number of generic families.
+    pub const COUNT: usize = 9;
+
     /// Parses a generic font family from CSS style names.
     pub fn parse(family: &str) -> Option<Self> {
         let mut s = LowercaseString::new();
@@ -65,6 +258,7 @@ impl GenericFamily {
             "system ui" => Self::SystemUI,
             "math" => Self::Math,
             "emoji" => Self::Emoji,
+            "fangsong" => Self::FangSong,
             _ => return None,
         })
     }
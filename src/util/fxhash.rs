@@ -30,7 +30,7 @@
 use core::default::Default;
 use core::hash::{BuildHasherDefault, Hasher};
 use core::ops::BitXor;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 /// A builder for default Fx hashers.
 pub type FxBuildHasher = BuildHasherDefault<FxHasher>;
@@ -45,7 +45,7 @@ pub type FxHashMap<K, V> = HashMap<K, V, FxBuildHasher>;
 ///
 /// Note: Use `FxHashSet::default()`, not `new()` to create a new `FxHashSet`.
 /// To create with a reserved capacity, use `FxHashSet::with_capacity_and_hasher(num, Default::default())`.
-// pub type FxHashSet<V> = HashSet<V, FxBuildHasher>;
+pub type FxHashSet<V> = HashSet<V, FxBuildHasher>;
 
 const ROTATE: u32 = 5;
 const SEED64: u64 = 0x51_7c_c1_b7_27_22_0a_95;
@@ -0,0 +1,215 @@
+//! Glob patterns for matching file paths.
+
+use std::path::{Path, MAIN_SEPARATOR};
+
+/// Compiled glob pattern.
+///
+/// Supports `?`, `*` (which does not match path separators), `**` (which
+/// does) and character classes such as `[a-z]` or `[!0-9]`. Patterns
+/// without a separator are matched against the file name only while all
+/// other patterns are matched against the full path.
+///
+/// Fontconfig patterns, compiled with [`Glob::separator_insensitive`], only
+/// support `?` and `*`, both of which also match path separators, and are
+/// always matched against the full path.
+#[derive(Clone, Debug)]
+pub struct Glob {
+    tokens: Vec<Token>,
+    file_name: bool,
+}
+
+#[derive(Clone, Debug)]
+enum Token {
+    Char(char),
+    Any,
+    /// `?` in a fontconfig pattern, which also matches a separator.
+    AnyChar,
+    Star,
+    DoubleStar,
+    /// `**/`, which matches zero or more complete path components.
+    Dirs,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl Glob {
+    /// Compiles the specified pattern.
+    pub fn new(pattern: &str) -> Self {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            i += 1;
+            match c {
+                '?' => tokens.push(Token::Any),
+                '*' if chars.get(i) == Some(&'*') => {
+                    i += 1;
+                    if chars.get(i).copied().is_some_and(is_separator) {
+                        i += 1;
+                        tokens.push(Token::Dirs);
+                    } else {
+                        tokens.push(Token::DoubleStar);
+                    }
+                }
+                '*' => tokens.push(Token::Star),
+                '[' => match parse_class(&chars[i..]) {
+                    Some((token, len)) => {
+                        tokens.push(token);
+                        i += len;
+                    }
+                    None => tokens.push(Token::Char('[')),
+                },
+                _ => tokens.push(Token::Char(c)),
+            }
+        }
+        Self {
+            tokens,
+            file_name: !chars.iter().copied().any(is_separator),
+        }
+    }
+
+    /// Compiles the specified fontconfig pattern.
+    pub fn separator_insensitive(pattern: &str) -> Self {
+        let tokens = pattern
+            .chars()
+            .map(|c| match c {
+                '?' => Token::AnyChar,
+                '*' => Token::DoubleStar,
+                _ => Token::Char(c),
+            })
+            .collect();
+        Self {
+            tokens,
+            file_name: false,
+        }
+    }
+
+    /// Returns true if the path matches the pattern.
+    pub fn matches(&self, path: &Path) -> bool {
+        let text = if self.file_name {
+            match path.file_name() {
+                Some(name) => name.to_string_lossy(),
+                None => return false,
+            }
+        } else {
+            path.to_string_lossy()
+        };
+        let text: Vec<char> = text.chars().collect();
+        match_tokens(&self.tokens, &text)
+    }
+}
+
+fn is_separator(c: char) -> bool {
+    c == '/' || c == MAIN_SEPARATOR
+}
+
+/// Parses a character class following an opening bracket. Returns the
+/// token and the number of characters consumed, including the closing
+/// bracket.
+fn parse_class(chars: &[char]) -> Option<(Token, usize)> {
+    let mut i = 0;
+    let negated = matches!(chars.first(), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+    let mut ranges = Vec::new();
+    // A closing bracket at the start of the class is a literal.
+    let start = i;
+    loop {
+        let c = *chars.get(i)?;
+        if c == ']' && i > start {
+            return Some((Token::Class { negated, ranges }, i + 1));
+        }
+        if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|&end| end != ']') {
+            ranges.push((c, chars[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((c, c));
+            i += 1;
+        }
+    }
+}
+
+fn match_tokens(tokens: &[Token], text: &[char]) -> bool {
+    let token = match tokens.first() {
+        Some(token) => token,
+        None => return text.is_empty(),
+    };
+    let rest = &tokens[1..];
+    match token {
+        Token::Star => {
+            for i in 0..=text.len() {
+                if match_tokens(rest, &text[i..]) {
+                    return true;
+                }
+                if i < text.len() && is_separator(text[i]) {
+                    break;
+                }
+            }
+            false
+        }
+        Token::DoubleStar => (0..=text.len()).any(|i| match_tokens(rest, &text[i..])),
+        Token::Dirs => {
+            match_tokens(rest, text)
+                || (1..=text.len())
+                    .any(|i| is_separator(text[i - 1]) && match_tokens(rest, &text[i..]))
+        }
+        _ => {
+            let c = match text.first() {
+                Some(&c) => c,
+                None => return false,
+            };
+            let matched = match token {
+                Token::Char(expected) => {
+                    c == *expected || (is_separator(c) && is_separator(*expected))
+                }
+                Token::Any => !is_separator(c),
+                Token::AnyChar => true,
+                Token::Class { negated, ranges } => {
+                    !is_separator(c)
+                        && ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negated
+                }
+                _ => false,
+            };
+            matched && match_tokens(rest, &text[1..])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_matches() {
+        let m = |pattern: &str, path: &str| Glob::new(pattern).matches(Path::new(path));
+        assert!(m(
+            "/usr/share/fonts/X11/**",
+            "/usr/share/fonts/X11/misc/6x13.pcf.gz"
+        ));
+        assert!(!m(
+            "/usr/share/fonts/X11/**",
+            "/usr/share/fonts/truetype/a.ttf"
+        ));
+        assert!(m("/usr/**/*.ttf", "/usr/a.ttf"));
+        assert!(m("/usr/**/*.ttf", "/usr/share/fonts/a.ttf"));
+        assert!(!m("/usr/*.ttf", "/usr/share/a.ttf"));
+        assert!(m("*.pcf.gz", "/usr/share/fonts/X11/misc/6x13.pcf.gz"));
+        assert!(m("Noto*[!0-9].ttf", "/fonts/NotoSansA.ttf"));
+        assert!(!m("Noto*[!0-9].ttf", "/fonts/NotoSans3.ttf"));
+        assert!(m("font?.otf", "/fonts/font1.otf"));
+        assert!(!m("font?.otf", "/fonts/font12.otf"));
+        let m = |pattern: &str, path: &str| {
+            Glob::separator_insensitive(pattern).matches(Path::new(path))
+        };
+        assert!(m("/usr/share/fonts/*", "/usr/share/fonts/X11/a.pcf"));
+        assert!(m("*/Nimbus?ans-*.otb", "/fonts/NimbusSans-Bold.otb"));
+        assert!(m("/fonts?a.pcf", "/fonts/a.pcf"));
+        assert!(!m("*.pcf", "/fonts/a.pcf.gz"));
+        assert!(!m("a.pcf", "/fonts/a.pcf"));
+        assert!(m("[a].pcf", "[a].pcf"));
+    }
+}
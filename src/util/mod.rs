@@ -1,3 +1,6 @@
 pub mod atomic;
 pub mod fxhash;
+pub mod glob;
 pub mod string;
+#[cfg(test)]
+pub mod test_font;
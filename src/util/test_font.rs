@@ -0,0 +1,449 @@
+//! Minimal fonts generated for tests.
+
+use std::path::PathBuf;
+use std::sync::Once;
+
+/// Description of a generated TrueType font.
+///
+/// Each mapped character has a rectangular glyph with the specified advance.
+/// Composite glyphs reference the glyph of another character.
+#[derive(Clone)]
+pub struct TestFont {
+    pub family: String,
+    pub subfamily: String,
+    pub typographic_family: Option<String>,
+    pub typographic_subfamily: Option<String>,
+    pub postscript_name: Option<String>,
+    pub manufacturer: Option<String>,
+    pub vendor: [u8; 4],
+    pub weight: u16,
+    pub width: u16,
+    pub italic: bool,
+    /// Font revision as a 16.16 fixed point number.
+    pub revision: u32,
+    pub fixed_pitch: bool,
+    /// Whether the font has glyph outlines. Fonts without outlines have no
+    /// `glyf` and `loca` tables.
+    pub outlines: bool,
+    pub chars: Vec<(char, u16)>,
+    /// Characters mapped to a composite of the glyph of another character.
+    pub composites: Vec<(char, char)>,
+    /// Variation axes as tag, minimum, default and maximum values.
+    pub axes: Vec<([u8; 4], f32, f32, f32)>,
+    /// Named instances as subfamily name and axis values.
+    pub instances: Vec<(String, Vec<f32>)>,
+    /// Additional tables.
+    pub tables: Vec<([u8; 4], Vec<u8>)>,
+}
+
+impl TestFont {
+    /// Creates a regular font with a family name and glyphs for the ASCII
+    /// letters.
+    pub fn new(family: &str) -> Self {
+        Self {
+            family: family.into(),
+            subfamily: "Regular".into(),
+            typographic_family: None,
+            typographic_subfamily: None,
+            postscript_name: None,
+            manufacturer: None,
+            vendor: *b"TEST",
+            weight: 400,
+            width: 5,
+            italic: false,
+            revision: 0x10000,
+            fixed_pitch: false,
+            outlines: true,
+            chars: ('A'..='Z')
+                .chain('a'..='z')
+                .map(|ch| (ch, if ch.is_uppercase() { 700 } else { 500 }))
+                .collect(),
+            composites: Vec::new(),
+            axes: Vec::new(),
+            instances: Vec::new(),
+            tables: Vec::new(),
+        }
+    }
+
+    /// Returns the font data.
+    pub fn build(&self) -> Vec<u8> {
+        sfnt(&self.sfnt_tables())
+    }
+
+    /// Returns the tables of the font, sorted by tag.
+    pub fn sfnt_tables(&self) -> Vec<([u8; 4], Vec<u8>)> {
+        // Glyph zero is an empty .notdef glyph.
+        let mut glyphs = vec![(None, 500)];
+        glyphs.extend(self.chars.iter().map(|&(ch, advance)| (Some(ch), advance)));
+        glyphs.extend(
+            self.composites
+                .iter()
+                .map(|&(ch, base)| (Some(ch), self.advance(base))),
+        );
+        let mut tables = vec![
+            (*b"head", self.head()),
+            (*b"hhea", hhea(glyphs.len())),
+            (*b"maxp", self.maxp(glyphs.len())),
+            (*b"OS/2", self.os2()),
+            (*b"post", self.post()),
+            (*b"name", self.name()),
+            (*b"cmap", cmap(&glyphs)),
+        ];
+        let mut hmtx = Vec::new();
+        for (i, &(_, advance)) in glyphs.iter().enumerate() {
+            hmtx.extend_from_slice(&advance.to_be_bytes());
+            hmtx.extend_from_slice(&self.x_min(i).to_be_bytes());
+        }
+        tables.push((*b"hmtx", hmtx));
+        if self.outlines {
+            let (glyf, loca) = self.glyf(&glyphs);
+            tables.push((*b"glyf", glyf));
+            tables.push((*b"loca", loca));
+        }
+        if !self.axes.is_empty() {
+            tables.push((*b"fvar", self.fvar()));
+        }
+        tables.extend(self.tables.iter().cloned());
+        tables.sort_by_key(|(tag, _)| *tag);
+        tables
+    }
+
+    fn advance(&self, ch: char) -> u16 {
+        self.chars
+            .iter()
+            .find(|(c, _)| *c == ch)
+            .map_or(500, |(_, advance)| *advance)
+    }
+
+    /// Returns the minimum x coordinate of a glyph, which is also its left
+    /// side bearing.
+    fn x_min(&self, glyph: usize) -> i16 {
+        match glyph {
+            0 => 0,
+            _ if glyph <= self.chars.len() => 50,
+            _ => 60,
+        }
+    }
+
+    fn head(&self) -> Vec<u8> {
+        let mut head = vec![0u8; 54];
+        head[0..4].copy_from_slice(&0x10000u32.to_be_bytes());
+        head[4..8].copy_from_slice(&self.revision.to_be_bytes());
+        head[12..16].copy_from_slice(&0x5F0F_3CF5u32.to_be_bytes());
+        head[18..20].copy_from_slice(&1000u16.to_be_bytes());
+        let mac_style = (self.weight >= 700) as u16 | (self.italic as u16) << 1;
+        head[44..46].copy_from_slice(&mac_style.to_be_bytes());
+        // Long loca offsets.
+        head[50..52].copy_from_slice(&1u16.to_be_bytes());
+        head
+    }
+
+    fn maxp(&self, num_glyphs: usize) -> Vec<u8> {
+        let (version, len) = if self.outlines {
+            (0x10000u32, 32)
+        } else {
+            (0x5000, 6)
+        };
+        let mut maxp = vec![0u8; len];
+        maxp[0..4].copy_from_slice(&version.to_be_bytes());
+        maxp[4..6].copy_from_slice(&(num_glyphs as u16).to_be_bytes());
+        maxp
+    }
+
+    fn os2(&self) -> Vec<u8> {
+        let mut os2 = vec![0u8; 96];
+        os2[0..2].copy_from_slice(&4u16.to_be_bytes());
+        os2[4..6].copy_from_slice(&self.weight.to_be_bytes());
+        os2[6..8].copy_from_slice(&self.width.to_be_bytes());
+        // Latin text PANOSE family kind and proportion.
+        os2[32] = 2;
+        os2[35] = if self.fixed_pitch { 9 } else { 3 };
+        os2[58..62].copy_from_slice(&self.vendor);
+        let selection = if self.italic {
+            1
+        } else if self.weight >= 700 {
+            1 << 5
+        } else {
+            1 << 6
+        };
+        os2[62..64].copy_from_slice(&(selection as u16).to_be_bytes());
+        os2
+    }
+
+    fn post(&self) -> Vec<u8> {
+        let mut post = vec![0u8; 32];
+        post[0..4].copy_from_slice(&0x30000u32.to_be_bytes());
+        post[12..16].copy_from_slice(&(self.fixed_pitch as u32).to_be_bytes());
+        post
+    }
+
+    fn name(&self) -> Vec<u8> {
+        let full_name = format!("{} {}", self.family, self.subfamily);
+        let postscript_name = self
+            .postscript_name
+            .clone()
+            .unwrap_or_else(|| full_name.replace(' ', "-"));
+        let mut names = vec![
+            (1, self.family.clone()),
+            (2, self.subfamily.clone()),
+            (4, full_name),
+            (6, postscript_name),
+        ];
+        if let Some(manufacturer) = &self.manufacturer {
+            names.push((8, manufacturer.clone()));
+        }
+        if let Some(family) = &self.typographic_family {
+            names.push((16, family.clone()));
+        }
+        if let Some(subfamily) = &self.typographic_subfamily {
+            names.push((17, subfamily.clone()));
+        }
+        for (i, (name, _)) in self.instances.iter().enumerate() {
+            names.push((256 + i as u16, name.clone()));
+        }
+        let mut records = Vec::new();
+        let mut strings = Vec::new();
+        for (id, name) in &names {
+            let encoded: Vec<u8> = name.encode_utf16().flat_map(u16::to_be_bytes).collect();
+            for value in [3, 1, 0x409, *id, encoded.len() as u16, strings.len() as u16] {
+                records.extend_from_slice(&value.to_be_bytes());
+            }
+            strings.extend_from_slice(&encoded);
+        }
+        let mut name = Vec::new();
+        for value in [0, names.len() as u16, 6 + records.len() as u16] {
+            name.extend_from_slice(&value.to_be_bytes());
+        }
+        name.extend_from_slice(&records);
+        name.extend_from_slice(&strings);
+        name
+    }
+
+    fn glyf(&self, glyphs: &[(Option<char>, u16)]) -> (Vec<u8>, Vec<u8>) {
+        let mut glyf = Vec::new();
+        let mut loca = Vec::new();
+        for (i, &(_, advance)) in glyphs.iter().enumerate() {
+            loca.extend_from_slice(&(glyf.len() as u32).to_be_bytes());
+            let x_max = advance as i16 - 50;
+            if i == 0 {
+                continue;
+            }
+            if i <= self.chars.len() {
+                // A quadrilateral with one diagonal edge.
+                let points = [(50, 0), (50, 700), (x_max, 700), (x_max - 100, 0)];
+                for value in [1, 50, 0, x_max, 700, 3] {
+                    glyf.extend_from_slice(&(value as i16).to_be_bytes());
+                }
+                // No instructions.
+                glyf.extend_from_slice(&[0; 2]);
+                let mut xs = Vec::new();
+                let mut ys = Vec::new();
+                let (mut last_x, mut last_y) = (0i16, 0i16);
+                for (x, y) in points {
+                    let mut flag = 1u8;
+                    let (dx, dy) = (x - last_x, y - last_y);
+                    (last_x, last_y) = (x, y);
+                    if dx == 0 {
+                        flag |= 0x10;
+                    } else if dx.abs() < 256 {
+                        flag |= 0x02 | if dx > 0 { 0x10 } else { 0 };
+                        xs.push(dx.unsigned_abs() as u8);
+                    } else {
+                        xs.extend_from_slice(&dx.to_be_bytes());
+                    }
+                    if dy == 0 {
+                        flag |= 0x20;
+                    } else if dy.abs() < 256 {
+                        flag |= 0x04 | if dy > 0 { 0x20 } else { 0 };
+                        ys.push(dy.unsigned_abs() as u8);
+                    } else {
+                        ys.extend_from_slice(&dy.to_be_bytes());
+                    }
+                    glyf.push(flag);
+                }
+                glyf.extend_from_slice(&xs);
+                glyf.extend_from_slice(&ys);
+            } else {
+                let base = self.composites[i - self.chars.len() - 1].1;
+                let base = 1 + self.chars.iter().position(|(ch, _)| *ch == base).unwrap();
+                for value in [-1, 60, 0, x_max + 10, 700] {
+                    glyf.extend_from_slice(&(value as i16).to_be_bytes());
+                }
+                // Word sized x and y offsets.
+                for value in [0x0003, base as i16, 10, 0] {
+                    glyf.extend_from_slice(&(value as i16).to_be_bytes());
+                }
+            }
+            while glyf.len() % 4 != 0 {
+                glyf.push(0);
+            }
+        }
+        loca.extend_from_slice(&(glyf.len() as u32).to_be_bytes());
+        (glyf, loca)
+    }
+
+    fn fvar(&self) -> Vec<u8> {
+        let axis_count = self.axes.len() as u16;
+        let instance_size = 4 + 4 * axis_count;
+        let mut fvar = Vec::new();
+        let fixed = |value: f32| ((value * 65536.) as i32).to_be_bytes();
+        for value in [1, 0, 16, 2, axis_count, 20, self.instances.len() as u16] {
+            fvar.extend_from_slice(&value.to_be_bytes());
+        }
+        fvar.extend_from_slice(&instance_size.to_be_bytes());
+        for (tag, min, default, max) in &self.axes {
+            fvar.extend_from_slice(tag);
+            for value in [min, default, max] {
+                fvar.extend_from_slice(&fixed(*value));
+            }
+            fvar.extend_from_slice(&[0; 4]);
+        }
+        for (i, (_, values)) in self.instances.iter().enumerate() {
+            fvar.extend_from_slice(&(256 + i as u16).to_be_bytes());
+            fvar.extend_from_slice(&[0; 2]);
+            for value in values {
+                fvar.extend_from_slice(&fixed(*value));
+            }
+        }
+        fvar
+    }
+}
+
+fn hhea(num_glyphs: usize) -> Vec<u8> {
+    let mut hhea = vec![0u8; 36];
+    hhea[0..4].copy_from_slice(&0x10000u32.to_be_bytes());
+    hhea[4..6].copy_from_slice(&800i16.to_be_bytes());
+    hhea[6..8].copy_from_slice(&(-200i16).to_be_bytes());
+    hhea[34..36].copy_from_slice(&(num_glyphs as u16).to_be_bytes());
+    hhea
+}
+
+/// Returns a cmap table with a format 12 subtable for the glyphs.
+fn cmap(glyphs: &[(Option<char>, u16)]) -> Vec<u8> {
+    let mut groups: Vec<(u32, u32)> = glyphs
+        .iter()
+        .enumerate()
+        .filter_map(|(i, (ch, _))| Some(((*ch)? as u32, i as u32)))
+        .collect();
+    groups.sort_unstable();
+    let mut cmap = Vec::new();
+    for value in [0u16, 1, 3, 10] {
+        cmap.extend_from_slice(&value.to_be_bytes());
+    }
+    cmap.extend_from_slice(&12u32.to_be_bytes());
+    cmap.extend_from_slice(&12u16.to_be_bytes());
+    cmap.extend_from_slice(&[0; 2]);
+    let len = 16 + 12 * groups.len() as u32;
+    for value in [len, 0, groups.len() as u32] {
+        cmap.extend_from_slice(&value.to_be_bytes());
+    }
+    for (ch, glyph) in groups {
+        for value in [ch, ch, glyph] {
+            cmap.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+    cmap
+}
+
+/// Writes an sfnt file with the specified tables, which must be sorted by
+/// tag.
+pub fn sfnt(tables: &[([u8; 4], Vec<u8>)]) -> Vec<u8> {
+    let mut data = Vec::new();
+    write_font(&mut data, tables);
+    data
+}
+
+#[cfg(feature = "woff")]
+/// Encodes a font as WOFF, compressing the tables that get smaller.
+pub fn woff(tables: &[([u8; 4], Vec<u8>)]) -> Vec<u8> {
+    let mut directory = Vec::new();
+    let mut data = Vec::new();
+    let start = 44 + 20 * tables.len();
+    for (tag, table) in tables {
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(table, 6);
+        let stored = if compressed.len() < table.len() {
+            &compressed
+        } else {
+            table
+        };
+        directory.extend_from_slice(tag);
+        for value in [start + data.len(), stored.len(), table.len(), 0] {
+            directory.extend_from_slice(&(value as u32).to_be_bytes());
+        }
+        data.extend_from_slice(stored);
+        data.resize((data.len() + 3) & !3, 0);
+    }
+    let mut woff = Vec::new();
+    woff.extend_from_slice(b"wOFF");
+    woff.extend_from_slice(&0x10000u32.to_be_bytes());
+    woff.extend_from_slice(&((start + data.len()) as u32).to_be_bytes());
+    woff.extend_from_slice(&(tables.len() as u16).to_be_bytes());
+    woff.resize(44, 0);
+    woff.extend_from_slice(&directory);
+    woff.extend_from_slice(&data);
+    woff
+}
+
+/// Writes a collection of fonts, each with its own copy of its tables.
+pub fn collection(fonts: &[Vec<([u8; 4], Vec<u8>)>]) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(b"ttcf");
+    data.extend_from_slice(&0x10000u32.to_be_bytes());
+    data.extend_from_slice(&(fonts.len() as u32).to_be_bytes());
+    let offsets = data.len();
+    data.resize(offsets + 4 * fonts.len(), 0);
+    for (i, tables) in fonts.iter().enumerate() {
+        let offset = data.len() as u32;
+        data[offsets + 4 * i..offsets + 4 * i + 4].copy_from_slice(&offset.to_be_bytes());
+        write_font(&mut data, tables);
+    }
+    data
+}
+
+fn write_font(data: &mut Vec<u8>, tables: &[([u8; 4], Vec<u8>)]) {
+    let start = data.len();
+    let num_tables = tables.len() as u16;
+    let entry_selector = 15 - num_tables.leading_zeros() as u16;
+    let search_range = 16 << entry_selector;
+    data.extend_from_slice(&0x10000u32.to_be_bytes());
+    for value in [
+        num_tables,
+        search_range,
+        entry_selector,
+        num_tables * 16 - search_range,
+    ] {
+        data.extend_from_slice(&value.to_be_bytes());
+    }
+    let mut offset = start + 12 + 16 * tables.len();
+    for (tag, table) in tables {
+        let checksum = table.chunks(4).fold(0u32, |sum, chunk| {
+            let mut word = [0u8; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            sum.wrapping_add(u32::from_be_bytes(word))
+        });
+        data.extend_from_slice(tag);
+        data.extend_from_slice(&checksum.to_be_bytes());
+        data.extend_from_slice(&(offset as u32).to_be_bytes());
+        data.extend_from_slice(&(table.len() as u32).to_be_bytes());
+        offset += (table.len() + 3) & !3;
+    }
+    for (_, table) in tables {
+        data.extend_from_slice(table);
+        data.resize((data.len() + 3) & !3, 0);
+    }
+}
+
+/// Returns an empty temporary directory for a test.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("font-index-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Keeps libraries built in tests from loading the fontconfig
+/// configuration, which would add the system fonts.
+pub fn isolate() {
+    static ISOLATE: Once = Once::new();
+    ISOLATE.call_once(|| std::env::set_var("FONTCONFIG_FILE", "/nonexistent"));
+}
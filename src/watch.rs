@@ -0,0 +1,213 @@
+//! Watching font directories for changes on Linux.
+
+use super::library::{FontLibrary, Inner};
+use crate::util::fxhash::{FxHashMap, FxHashSet};
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask, Watches};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::{Arc, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Interval without further changes after which the library is refreshed.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Stops watching when dropped.
+pub(super) struct Handle {
+    wake: Option<UnixStream>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        // Closing the stream wakes the thread, which then exits.
+        self.wake.take();
+        if let Some(thread) = self.thread.take() {
+            // The last reference to the library may be released by the
+            // thread itself while it refreshes.
+            if thread.thread().id() != thread::current().id() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+/// Starts watching the directories scanned for the specified library.
+///
+/// A thread waits for inotify events, coalesces them and refreshes the
+/// directories that changed. Dropping the returned handle stops the thread
+/// and waits for it to exit.
+pub(super) fn spawn(library: &FontLibrary) -> io::Result<Handle> {
+    let mut inotify = Inotify::init()?;
+    let mut watcher = Watcher {
+        watches: inotify.watches(),
+        watched: FxHashMap::default(),
+    };
+    watcher.update(library);
+    let (wake, stop) = UnixStream::pair()?;
+    let library = Arc::downgrade(&library.inner);
+    let thread = thread::Builder::new()
+        .name("font-index-watch".into())
+        .spawn(move || watcher.run(&mut inotify, &stop, &library))?;
+    Ok(Handle {
+        wake: Some(wake),
+        thread: Some(thread),
+    })
+}
+
+struct Watcher {
+    watches: Watches,
+    watched: FxHashMap<WatchDescriptor, PathBuf>,
+}
+
+impl Watcher {
+    /// Waits for changes until the stream is closed or the library has been
+    /// dropped.
+    fn run(&mut self, inotify: &mut Inotify, stop: &UnixStream, library: &Weak<Inner>) {
+        let mut buffer = [0u8; 4096];
+        let mut changed = FxHashSet::default();
+        let mut pending = false;
+        let mut overflow = false;
+        loop {
+            let mut fds = [
+                libc::pollfd {
+                    fd: inotify.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+                libc::pollfd {
+                    fd: stop.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+            ];
+            let timeout = if pending {
+                DEBOUNCE.as_millis() as libc::c_int
+            } else {
+                -1
+            };
+            let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+            if ready < 0 {
+                if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                break;
+            }
+            if fds[1].revents != 0 {
+                break;
+            }
+            if ready == 0 {
+                let library = match library.upgrade() {
+                    Some(inner) => FontLibrary { inner },
+                    None => break,
+                };
+                if overflow {
+                    library.refresh();
+                } else {
+                    library.refresh_dirs(&changed);
+                }
+                changed.clear();
+                overflow = false;
+                // Files may have been added to new directories before they
+                // were watched, so these are scanned again.
+                changed.extend(self.update(&library));
+                pending = !changed.is_empty();
+                continue;
+            }
+            match inotify.read_events(&mut buffer) {
+                Ok(events) => {
+                    for event in events {
+                        pending = true;
+                        if event.mask.contains(EventMask::Q_OVERFLOW) {
+                            overflow = true;
+                        } else if let Some(path) = self.watched.get(&event.wd) {
+                            changed.insert(path.clone());
+                        }
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(_) => break,
+            }
+        }
+    }
+
+    /// Synchronizes the watch list with the directories found by the last
+    /// scan of the library and returns the directories that were added.
+    fn update(&mut self, library: &FontLibrary) -> Vec<PathBuf> {
+        let config = library.inner.config.lock().unwrap();
+        let dirs: FxHashSet<&PathBuf> = config.scanned_dirs().collect();
+        let watches = &mut self.watches;
+        self.watched.retain(|wd, path| {
+            if dirs.contains(path) {
+                return true;
+            }
+            let _ = watches.remove(wd.clone());
+            false
+        });
+        let watched: FxHashSet<PathBuf> = self.watched.values().cloned().collect();
+        let mut added = Vec::new();
+        let mask = WatchMask::CREATE
+            | WatchMask::DELETE
+            | WatchMask::CLOSE_WRITE
+            | WatchMask::MOVED_FROM
+            | WatchMask::MOVED_TO
+            | WatchMask::DELETE_SELF
+            | WatchMask::MOVE_SELF;
+        for dir in dirs {
+            if watched.contains(dir) {
+                continue;
+            }
+            match watches.add(dir, mask) {
+                Ok(wd) => {
+                    self.watched.insert(wd, dir.clone());
+                    added.push(dir.clone());
+                }
+                Err(err) => log::debug!("failed to watch {:?}: {}", dir, err),
+            }
+        }
+        added
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::test_font::{self, TestFont};
+    use crate::{FontCache, FontLibrary};
+    use std::fs;
+    use std::time::Duration;
+
+    #[test]
+    fn watch_dir() {
+        test_font::isolate();
+        let dir = test_font::temp_dir("watch-dir");
+        fs::create_dir_all(dir.join("a")).unwrap();
+        fs::create_dir_all(dir.join("b")).unwrap();
+        fs::write(dir.join("a/Alpha.ttf"), TestFont::new("Alpha").build()).unwrap();
+        fs::write(dir.join("b/Beta.ttf"), TestFont::new("Beta").build()).unwrap();
+        let library = FontLibrary::builder().watch(true).add_dir(&dir).build();
+        let mut fonts = FontCache::new(library.clone());
+        let receiver = library.subscribe();
+        // Changes in quick succession are refreshed together.
+        for family in ["Gamma", "Delta"] {
+            let path = dir.join("a").join(format!("{}.ttf", family));
+            fs::write(path, TestFont::new(family).build()).unwrap();
+        }
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(10)), Ok(1));
+        assert!(fonts.query("Gamma", ()).is_none());
+        assert!(fonts.update());
+        for family in ["Alpha", "Beta", "Gamma", "Delta"] {
+            assert!(fonts.query(family, ()).is_some(), "{}", family);
+        }
+        assert!(receiver.recv_timeout(Duration::from_secs(1)).is_err());
+        // Dropping the handle waits for the thread to exit, after which
+        // changes are no longer picked up.
+        let handle = library.inner.watch.lock().unwrap().take();
+        drop(handle);
+        fs::write(dir.join("a/Epsilon.ttf"), TestFont::new("Epsilon").build()).unwrap();
+        assert!(receiver.recv_timeout(Duration::from_secs(1)).is_err());
+        assert_eq!(library.generation(), 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
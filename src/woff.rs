@@ -0,0 +1,937 @@
+//! Decoding of WOFF and WOFF2 files to sfnt data.
+
+use std::convert::TryInto;
+use std::io::Read;
+
+const WOFF: u32 = u32::from_be_bytes(*b"wOFF");
+const WOFF2: u32 = u32::from_be_bytes(*b"wOF2");
+const TTCF: u32 = u32::from_be_bytes(*b"ttcf");
+const GLYF: u32 = u32::from_be_bytes(*b"glyf");
+const LOCA: u32 = u32::from_be_bytes(*b"loca");
+const HMTX: u32 = u32::from_be_bytes(*b"hmtx");
+const HHEA: u32 = u32::from_be_bytes(*b"hhea");
+const MAXP: u32 = u32::from_be_bytes(*b"maxp");
+
+/// Tags that may be encoded as an index in a WOFF2 table directory.
+const KNOWN_TAGS: [&[u8; 4]; 63] = [
+    b"cmap", b"head", b"hhea", b"hmtx", b"maxp", b"name", b"OS/2", b"post", b"cvt ", b"fpgm",
+    b"glyf", b"loca", b"prep", b"CFF ", b"VORG", b"EBDT", b"EBLC", b"gasp", b"hdmx", b"kern",
+    b"LTSH", b"PCLT", b"VDMX", b"vhea", b"vmtx", b"BASE", b"GDEF", b"GPOS", b"GSUB", b"EBSC",
+    b"JSTF", b"MATH", b"CBDT", b"CBLC", b"COLR", b"CPAL", b"SVG ", b"sbix", b"acnt", b"avar",
+    b"bdat", b"bloc", b"bsln", b"cvar", b"fdsc", b"feat", b"fmtx", b"fvar", b"gvar", b"hsty",
+    b"just", b"lcar", b"mort", b"morx", b"opbd", b"prop", b"trak", b"Zapf", b"Silf", b"Glat",
+    b"Gloc", b"Feat", b"Sill",
+];
+
+/// Decodes WOFF or WOFF2 data to sfnt data. Returns `None` if the data is
+/// not a web font or is malformed.
+pub fn decode(data: &[u8]) -> Option<Vec<u8>> {
+    match Stream::new(data).u32()? {
+        WOFF => decode_woff(data),
+        WOFF2 => decode_woff2(data),
+        _ => None,
+    }
+}
+
+fn decode_woff(data: &[u8]) -> Option<Vec<u8>> {
+    let mut s = Stream::new(data);
+    s.skip(4)?;
+    let flavor = s.u32()?;
+    s.skip(4)?;
+    let num_tables = s.u16()? as usize;
+    s.skip(30)?;
+    let mut tags = Vec::with_capacity(num_tables);
+    let mut tables = Vec::with_capacity(num_tables);
+    for _ in 0..num_tables {
+        let tag = s.u32()?;
+        let offset = s.u32()? as usize;
+        let comp_len = s.u32()? as usize;
+        let orig_len = s.u32()? as usize;
+        s.skip(4)?;
+        let comp = data.get(offset..offset.checked_add(comp_len)?)?;
+        let table = if comp_len < orig_len {
+            miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(comp, orig_len).ok()?
+        } else {
+            comp.to_vec()
+        };
+        if table.len() != orig_len {
+            return None;
+        }
+        tags.push(tag);
+        tables.push(table);
+    }
+    let indices = (0..num_tables).collect();
+    Some(build_sfnt(&[(flavor, indices)], &tags, &tables))
+}
+
+/// Entry in a WOFF2 table directory.
+struct Woff2Table {
+    tag: u32,
+    orig_len: usize,
+    transformed: bool,
+    range: core::ops::Range<usize>,
+}
+
+fn decode_woff2(data: &[u8]) -> Option<Vec<u8>> {
+    let mut s = Stream::new(data);
+    s.skip(4)?;
+    let flavor = s.u32()?;
+    s.skip(4)?;
+    let num_tables = s.u16()? as usize;
+    s.skip(6)?;
+    let compressed_len = s.u32()? as usize;
+    s.skip(24)?;
+    let mut entries = Vec::with_capacity(num_tables);
+    let mut offset = 0usize;
+    for _ in 0..num_tables {
+        let flags = s.u8()?;
+        let tag = if flags & 0x3F == 0x3F {
+            s.u32()?
+        } else {
+            u32::from_be_bytes(**KNOWN_TAGS.get((flags & 0x3F) as usize)?)
+        };
+        let version = flags >> 6;
+        let orig_len = s.base128()? as usize;
+        // The glyf and loca tables use version 3 for the null transform
+        // while all other tables use version 0.
+        let transformed = if tag == GLYF || tag == LOCA {
+            version != 3
+        } else {
+            version != 0
+        };
+        let len = if transformed {
+            s.base128()? as usize
+        } else {
+            orig_len
+        };
+        entries.push(Woff2Table {
+            tag,
+            orig_len,
+            transformed,
+            range: offset..offset.checked_add(len)?,
+        });
+        offset += len;
+    }
+    let fonts = if flavor == TTCF {
+        s.skip(4)?;
+        let num_fonts = s.u255()? as usize;
+        let mut fonts = Vec::with_capacity(num_fonts);
+        for _ in 0..num_fonts {
+            let count = s.u255()? as usize;
+            let flavor = s.u32()?;
+            let mut indices = Vec::with_capacity(count);
+            for _ in 0..count {
+                let index = s.u255()? as usize;
+                if index >= num_tables {
+                    return None;
+                }
+                indices.push(index);
+            }
+            fonts.push((flavor, indices));
+        }
+        if fonts.is_empty() {
+            return None;
+        }
+        fonts
+    } else {
+        vec![(flavor, (0..num_tables).collect())]
+    };
+    let start = s.pos;
+    let compressed = data.get(start..start.checked_add(compressed_len)?)?;
+    // The table lengths are not trusted for the initial capacity or to
+    // decompress past the end of the tables.
+    let mut stream = Vec::new();
+    brotli_decompressor::Decompressor::new(compressed, 4096)
+        .take(offset as u64)
+        .read_to_end(&mut stream)
+        .ok()?;
+    if stream.len() < offset {
+        return None;
+    }
+    let mut tables: Vec<Option<Vec<u8>>> = vec![None; num_tables];
+    for (_, indices) in &fonts {
+        let find = |tag| indices.iter().copied().find(|&i| entries[i].tag == tag);
+        let mut x_mins = None;
+        for &i in indices {
+            let entry = &entries[i];
+            if tables[i].is_some() {
+                continue;
+            }
+            if !entry.transformed {
+                tables[i] = Some(stream[entry.range.clone()].to_vec());
+            } else if entry.tag == GLYF {
+                let loca = find(LOCA)?;
+                let glyf = reconstruct_glyf(&stream[entry.range.clone()])?;
+                if glyf.loca.len() != entries[loca].orig_len {
+                    return None;
+                }
+                tables[i] = Some(glyf.glyf);
+                tables[loca] = Some(glyf.loca);
+                x_mins = Some(glyf.x_mins);
+            }
+        }
+        for &i in indices {
+            let entry = &entries[i];
+            if tables[i].is_some() {
+                continue;
+            }
+            if entry.tag != HMTX {
+                return None;
+            }
+            let hhea = tables[find(HHEA)?].as_deref()?;
+            let maxp = tables[find(MAXP)?].as_deref()?;
+            let num_h_metrics = Stream::new(hhea.get(34..)?).u16()? as usize;
+            let num_glyphs = Stream::new(maxp.get(4..)?).u16()? as usize;
+            let hmtx = reconstruct_hmtx(
+                &stream[entry.range.clone()],
+                num_glyphs,
+                num_h_metrics,
+                x_mins.as_deref()?,
+            )?;
+            tables[i] = Some(hmtx);
+        }
+    }
+    let tags: Vec<_> = entries.iter().map(|entry| entry.tag).collect();
+    let tables: Vec<_> = tables
+        .into_iter()
+        .map(|table| table.unwrap_or_default())
+        .collect();
+    Some(build_sfnt(&fonts, &tags, &tables))
+}
+
+/// Glyph and location tables reconstructed from a transformed glyf table.
+struct Glyf {
+    glyf: Vec<u8>,
+    loca: Vec<u8>,
+    x_mins: Vec<i16>,
+}
+
+fn reconstruct_glyf(data: &[u8]) -> Option<Glyf> {
+    let mut s = Stream::new(data);
+    s.skip(2)?;
+    let options = s.u16()?;
+    let num_glyphs = s.u16()? as usize;
+    let index_format = s.u16()?;
+    let mut streams = [Stream::new(&[]); 7];
+    let mut offset = 36usize;
+    for stream in &mut streams {
+        let len = s.u32()? as usize;
+        *stream = Stream::new(data.get(offset..offset.checked_add(len)?)?);
+        offset += len;
+    }
+    let [mut contours, mut points, mut flags, mut glyphs, mut composites, bboxes, mut instructions] =
+        streams;
+    let bitmap_len = ((num_glyphs + 31) >> 5) << 2;
+    let bbox_bitmap = bboxes.data.get(..bitmap_len)?;
+    let mut bboxes = Stream::new(&bboxes.data[bitmap_len..]);
+    let overlap_bitmap = if options & 1 != 0 {
+        data.get(offset..offset + ((num_glyphs + 7) >> 3))
+    } else {
+        None
+    };
+    let mut glyf = Vec::new();
+    let mut offsets = Vec::with_capacity(num_glyphs + 1);
+    let mut x_mins = Vec::with_capacity(num_glyphs);
+    let mut end_points = Vec::new();
+    let mut coords = Vec::new();
+    for i in 0..num_glyphs {
+        offsets.push(glyf.len());
+        let has_bbox = bbox_bitmap[i >> 3] & (0x80 >> (i & 7)) != 0;
+        let num_contours = contours.i16()?;
+        if num_contours == 0 {
+            if has_bbox {
+                return None;
+            }
+            x_mins.push(0);
+            continue;
+        }
+        let bbox_pos = glyf.len() + 2;
+        glyf.extend_from_slice(&num_contours.to_be_bytes());
+        glyf.extend_from_slice(&[0; 8]);
+        let bbox = if num_contours < 0 {
+            if !has_bbox {
+                return None;
+            }
+            let start = composites.pos;
+            let mut have_instructions = false;
+            loop {
+                let component = composites.u16()?;
+                have_instructions |= component & 0x100 != 0;
+                let mut len = 2 + if component & 1 != 0 { 4 } else { 2 };
+                if component & 8 != 0 {
+                    len += 2;
+                } else if component & 0x40 != 0 {
+                    len += 4;
+                } else if component & 0x80 != 0 {
+                    len += 8;
+                }
+                composites.skip(len)?;
+                if component & 0x20 == 0 {
+                    break;
+                }
+            }
+            glyf.extend_from_slice(&composites.data[start..composites.pos]);
+            if have_instructions {
+                let len = glyphs.u255()?;
+                glyf.extend_from_slice(&len.to_be_bytes());
+                glyf.extend_from_slice(instructions.bytes(len as usize)?);
+            }
+            read_bbox(&mut bboxes)?
+        } else {
+            end_points.clear();
+            let mut total = 0u32;
+            for _ in 0..num_contours {
+                total += points.u255()? as u32;
+                if total > 0x10000 {
+                    return None;
+                }
+                end_points.push(total.checked_sub(1)? as u16);
+            }
+            coords.clear();
+            let (mut x, mut y) = (0i32, 0i32);
+            for _ in 0..total {
+                let flag = flags.u8()?;
+                let on_curve = flag & 0x80 == 0;
+                let (dx, dy) = decode_triplet(flag & 0x7F, &mut glyphs)?;
+                x = x.wrapping_add(dx);
+                y = y.wrapping_add(dy);
+                coords.push((x, y, on_curve));
+            }
+            for end in &end_points {
+                glyf.extend_from_slice(&end.to_be_bytes());
+            }
+            let len = glyphs.u255()?;
+            glyf.extend_from_slice(&len.to_be_bytes());
+            glyf.extend_from_slice(instructions.bytes(len as usize)?);
+            let overlap = overlap_bitmap.is_some_and(|bits| bits[i >> 3] & (0x80 >> (i & 7)) != 0);
+            write_points(&mut glyf, &coords, overlap);
+            if has_bbox {
+                read_bbox(&mut bboxes)?
+            } else {
+                let mut bbox = [0i16; 4];
+                if let Some(&(x, y, _)) = coords.first() {
+                    let (mut x_min, mut y_min, mut x_max, mut y_max) = (x, y, x, y);
+                    for &(x, y, _) in &coords {
+                        x_min = x_min.min(x);
+                        y_min = y_min.min(y);
+                        x_max = x_max.max(x);
+                        y_max = y_max.max(y);
+                    }
+                    bbox = [x_min as i16, y_min as i16, x_max as i16, y_max as i16];
+                }
+                bbox
+            }
+        };
+        for (j, value) in bbox.iter().enumerate() {
+            glyf[bbox_pos + j * 2..bbox_pos + j * 2 + 2].copy_from_slice(&value.to_be_bytes());
+        }
+        x_mins.push(bbox[0]);
+        while glyf.len() % 4 != 0 {
+            glyf.push(0);
+        }
+    }
+    offsets.push(glyf.len());
+    let mut loca = Vec::new();
+    for offset in offsets {
+        if index_format == 0 {
+            // Short offsets can't address glyph data past 128 KiB.
+            if offset > 0x1FFFE {
+                return None;
+            }
+            loca.extend_from_slice(&((offset >> 1) as u16).to_be_bytes());
+        } else {
+            loca.extend_from_slice(&(offset as u32).to_be_bytes());
+        }
+    }
+    Some(Glyf { glyf, loca, x_mins })
+}
+
+fn read_bbox(s: &mut Stream) -> Option<[i16; 4]> {
+    Some([s.i16()?, s.i16()?, s.i16()?, s.i16()?])
+}
+
+/// Decodes a point delta from the glyph stream.
+fn decode_triplet(flag: u8, s: &mut Stream) -> Option<(i32, i32)> {
+    fn with_sign(flag: u8, value: i32) -> i32 {
+        if flag & 1 != 0 {
+            value
+        } else {
+            -value
+        }
+    }
+    let flag32 = flag as i32;
+    Some(if flag < 10 {
+        let b = s.u8()? as i32;
+        (0, with_sign(flag, ((flag32 & 14) << 7) + b))
+    } else if flag < 20 {
+        let b = s.u8()? as i32;
+        (with_sign(flag, (((flag32 - 10) & 14) << 7) + b), 0)
+    } else if flag < 84 {
+        let b0 = flag32 - 20;
+        let b1 = s.u8()? as i32;
+        (
+            with_sign(flag, 1 + (b0 & 0x30) + (b1 >> 4)),
+            with_sign(flag >> 1, 1 + ((b0 & 0x0C) << 2) + (b1 & 0x0F)),
+        )
+    } else if flag < 120 {
+        let b0 = flag32 - 84;
+        let (b1, b2) = (s.u8()? as i32, s.u8()? as i32);
+        (
+            with_sign(flag, 1 + ((b0 / 12) << 8) + b1),
+            with_sign(flag >> 1, 1 + (((b0 % 12) >> 2) << 8) + b2),
+        )
+    } else if flag < 124 {
+        let (b1, b2, b3) = (s.u8()? as i32, s.u8()? as i32, s.u8()? as i32);
+        (
+            with_sign(flag, (b1 << 4) + (b2 >> 4)),
+            with_sign(flag >> 1, ((b2 & 0x0F) << 8) + b3),
+        )
+    } else {
+        let b = s.bytes(4)?;
+        (
+            with_sign(flag, ((b[0] as i32) << 8) + b[1] as i32),
+            with_sign(flag >> 1, ((b[2] as i32) << 8) + b[3] as i32),
+        )
+    })
+}
+
+/// Writes the flags and coordinates of a simple glyph.
+fn write_points(glyf: &mut Vec<u8>, coords: &[(i32, i32, bool)], overlap: bool) {
+    let mut xs = Vec::new();
+    let mut ys = Vec::new();
+    let (mut last_x, mut last_y) = (0, 0);
+    for (i, &(x, y, on_curve)) in coords.iter().enumerate() {
+        let mut flag = on_curve as u8;
+        if i == 0 && overlap {
+            flag |= 0x40;
+        }
+        let (dx, dy) = (x.wrapping_sub(last_x), y.wrapping_sub(last_y));
+        (last_x, last_y) = (x, y);
+        if dx == 0 {
+            flag |= 0x10;
+        } else if dx.abs() < 256 {
+            flag |= 0x02 | if dx > 0 { 0x10 } else { 0 };
+            xs.push(dx.unsigned_abs() as u8);
+        } else {
+            xs.extend_from_slice(&(dx as i16).to_be_bytes());
+        }
+        if dy == 0 {
+            flag |= 0x20;
+        } else if dy.abs() < 256 {
+            flag |= 0x04 | if dy > 0 { 0x20 } else { 0 };
+            ys.push(dy.unsigned_abs() as u8);
+        } else {
+            ys.extend_from_slice(&(dy as i16).to_be_bytes());
+        }
+        glyf.push(flag);
+    }
+    glyf.extend_from_slice(&xs);
+    glyf.extend_from_slice(&ys);
+}
+
+fn reconstruct_hmtx(
+    data: &[u8],
+    num_glyphs: usize,
+    num_h_metrics: usize,
+    x_mins: &[i16],
+) -> Option<Vec<u8>> {
+    if num_h_metrics == 0 || num_h_metrics > num_glyphs || x_mins.len() < num_glyphs {
+        return None;
+    }
+    let mut s = Stream::new(data);
+    let flags = s.u8()?;
+    let mut advances = Vec::with_capacity(num_h_metrics);
+    for _ in 0..num_h_metrics {
+        advances.push(s.u16()?);
+    }
+    let mut hmtx = Vec::with_capacity(num_h_metrics * 4 + (num_glyphs - num_h_metrics) * 2);
+    for (i, x_min) in x_mins.iter().enumerate().take(num_glyphs) {
+        let explicit = if i < num_h_metrics {
+            flags & 1 == 0
+        } else {
+            flags & 2 == 0
+        };
+        let lsb = if explicit { s.i16()? } else { *x_min };
+        if let Some(advance) = advances.get(i) {
+            hmtx.extend_from_slice(&advance.to_be_bytes());
+        }
+        hmtx.extend_from_slice(&lsb.to_be_bytes());
+    }
+    Some(hmtx)
+}
+
+/// Writes an sfnt file or, for multiple fonts, a collection. Each font is
+/// described by its flavor and the indices of its tables.
+fn build_sfnt(fonts: &[(u32, Vec<usize>)], tags: &[u32], tables: &[Vec<u8>]) -> Vec<u8> {
+    let collection = fonts.len() > 1;
+    let mut pos = if collection { 12 + 4 * fonts.len() } else { 0 };
+    let mut dir_offsets = Vec::with_capacity(fonts.len());
+    for (_, indices) in fonts {
+        dir_offsets.push(pos);
+        pos += 12 + 16 * indices.len();
+    }
+    let mut out = vec![0u8; pos];
+    let mut table_offsets = vec![None; tables.len()];
+    for (_, indices) in fonts {
+        for &i in indices {
+            if table_offsets[i].is_none() {
+                table_offsets[i] = Some(out.len());
+                out.extend_from_slice(&tables[i]);
+                while out.len() % 4 != 0 {
+                    out.push(0);
+                }
+            }
+        }
+    }
+    if collection {
+        out[0..4].copy_from_slice(&TTCF.to_be_bytes());
+        out[4..8].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+        out[8..12].copy_from_slice(&(fonts.len() as u32).to_be_bytes());
+        for (i, offset) in dir_offsets.iter().enumerate() {
+            out[12 + i * 4..16 + i * 4].copy_from_slice(&(*offset as u32).to_be_bytes());
+        }
+    }
+    for ((flavor, indices), dir_offset) in fonts.iter().zip(dir_offsets) {
+        let mut sorted = indices.clone();
+        sorted.sort_by_key(|&i| tags[i]);
+        // The search fields are computed in 32 bits since they overflow for
+        // directories that are too large to be valid.
+        let num_tables = sorted.len() as u32;
+        let entry_selector = if num_tables == 0 {
+            0
+        } else {
+            31 - num_tables.leading_zeros()
+        };
+        let search_range = (1 << entry_selector) * 16;
+        let mut header = Vec::with_capacity(12 + 16 * sorted.len());
+        header.extend_from_slice(&flavor.to_be_bytes());
+        header.extend_from_slice(&(num_tables as u16).to_be_bytes());
+        header.extend_from_slice(&(search_range as u16).to_be_bytes());
+        header.extend_from_slice(&(entry_selector as u16).to_be_bytes());
+        header.extend_from_slice(
+            &((num_tables * 16).saturating_sub(search_range) as u16).to_be_bytes(),
+        );
+        for i in sorted {
+            header.extend_from_slice(&tags[i].to_be_bytes());
+            header.extend_from_slice(&checksum(&tables[i]).to_be_bytes());
+            header.extend_from_slice(&(table_offsets[i].unwrap_or(0) as u32).to_be_bytes());
+            header.extend_from_slice(&(tables[i].len() as u32).to_be_bytes());
+        }
+        out[dir_offset..dir_offset + header.len()].copy_from_slice(&header);
+    }
+    out
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut word = [0u8; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+#[derive(Copy, Clone)]
+struct Stream<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Stream<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Option<()> {
+        self.bytes(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_be_bytes(self.bytes(2)?.try_into().ok()?))
+    }
+
+    fn i16(&mut self) -> Option<i16> {
+        Some(self.u16()? as i16)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    /// Reads a variable length 255UInt16 value.
+    fn u255(&mut self) -> Option<u16> {
+        Some(match self.u8()? {
+            253 => self.u16()?,
+            254 => self.u8()? as u16 + 253 * 2,
+            255 => self.u8()? as u16 + 253,
+            code => code as u16,
+        })
+    }
+
+    /// Reads a variable length UIntBase128 value.
+    fn base128(&mut self) -> Option<u32> {
+        let mut value = 0u32;
+        for i in 0..5 {
+            let byte = self.u8()?;
+            if (i == 0 && byte == 0x80) || value & 0xFE00_0000 != 0 {
+                return None;
+            }
+            value = (value << 7) | (byte & 0x7F) as u32;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_font::{self, TestFont};
+    use swash::{FontDataRef, TableProvider};
+
+    type Tables = Vec<([u8; 4], Vec<u8>)>;
+
+    fn test_font(family: &str) -> TestFont {
+        TestFont {
+            composites: vec![('À', 'A')],
+            ..TestFont::new(family)
+        }
+    }
+
+    /// Encodes fonts as WOFF2, sharing identical tables between the fonts
+    /// of a collection. The glyf, loca and hmtx tables are transformed.
+    fn woff2(fonts: &[Tables]) -> Vec<u8> {
+        let mut tables: Tables = Vec::new();
+        let mut indices = Vec::new();
+        for font in fonts {
+            let mut font_indices = Vec::new();
+            for table in font {
+                let index = match tables.iter().position(|other| other == table) {
+                    Some(index) => index,
+                    None => {
+                        tables.push(table.clone());
+                        tables.len() - 1
+                    }
+                };
+                font_indices.push(index);
+            }
+            indices.push(font_indices);
+        }
+        let mut directory = Vec::new();
+        let mut stream = Vec::new();
+        for (tag, table) in &tables {
+            let known = KNOWN_TAGS.iter().position(|known| *known == tag).unwrap();
+            let transformed = match tag {
+                b"glyf" => transform_glyf(table),
+                b"loca" => Vec::new(),
+                b"hmtx" => {
+                    // Left side bearings are omitted since they are the
+                    // minimum x coordinates of the glyphs.
+                    let mut hmtx = vec![3];
+                    for metric in table.chunks(4) {
+                        hmtx.extend_from_slice(&metric[..2]);
+                    }
+                    hmtx
+                }
+                _ => table.clone(),
+            };
+            let version = if tag == b"hmtx" { 1 } else { 0 };
+            directory.push(known as u8 | version << 6);
+            write_base128(&mut directory, table.len() as u32);
+            if matches!(tag, b"glyf" | b"loca" | b"hmtx") {
+                write_base128(&mut directory, transformed.len() as u32);
+            }
+            stream.extend_from_slice(&transformed);
+        }
+        if fonts.len() > 1 {
+            directory.extend_from_slice(&0x10000u32.to_be_bytes());
+            write_u255(&mut directory, fonts.len() as u16);
+            for font_indices in &indices {
+                write_u255(&mut directory, font_indices.len() as u16);
+                directory.extend_from_slice(&0x10000u32.to_be_bytes());
+                for &index in font_indices {
+                    write_u255(&mut directory, index as u16);
+                }
+            }
+        }
+        let compressed = brotli_uncompressed(&stream);
+        let flavor = if fonts.len() > 1 { TTCF } else { 0x10000 };
+        let mut woff2 = Vec::new();
+        woff2.extend_from_slice(b"wOF2");
+        woff2.extend_from_slice(&flavor.to_be_bytes());
+        let len = 48 + directory.len() + compressed.len();
+        woff2.extend_from_slice(&(len as u32).to_be_bytes());
+        woff2.extend_from_slice(&(tables.len() as u16).to_be_bytes());
+        woff2.extend_from_slice(&[0; 6]);
+        woff2.extend_from_slice(&(compressed.len() as u32).to_be_bytes());
+        woff2.resize(48, 0);
+        woff2.extend_from_slice(&directory);
+        woff2.extend_from_slice(&compressed);
+        woff2
+    }
+
+    /// Transforms a glyf table with long loca offsets. The bounding box of
+    /// the first simple glyph is stored and the others are computed by the
+    /// decoder.
+    fn transform_glyf(glyf: &[u8]) -> Vec<u8> {
+        let mut glyphs = Vec::new();
+        let mut offset = 0;
+        while offset < glyf.len() {
+            let mut s = Stream::new(&glyf[offset..]);
+            let num_contours = s.i16().unwrap();
+            let bbox = read_bbox(&mut s).unwrap();
+            let len = if num_contours < 0 {
+                // A single component with word arguments.
+                10 + 8
+            } else {
+                let points = (0..num_contours).map(|_| s.u16().unwrap()).last().unwrap() + 1;
+                let instructions = s.u16().unwrap() as usize;
+                s.skip(instructions).unwrap();
+                let flags: Vec<_> = (0..points).map(|_| s.u8().unwrap()).collect();
+                for (short, same) in [(0x02, 0x10), (0x04, 0x20)] {
+                    for flag in &flags {
+                        match (flag & short != 0, flag & same != 0) {
+                            (true, _) => s.skip(1).unwrap(),
+                            (false, false) => s.skip(2).unwrap(),
+                            _ => {}
+                        }
+                    }
+                }
+                s.pos
+            };
+            glyphs.push((num_contours, bbox, &glyf[offset..offset + len]));
+            offset += (len + 3) & !3;
+        }
+        // Glyph zero is empty and has no data in the glyf table.
+        glyphs.insert(0, (0, [0; 4], &[]));
+        let mut streams = [(); 7].map(|_| Vec::new());
+        let [contours, points, flags, coords, composites, bboxes, instructions] = &mut streams;
+        let mut bbox_bitmap = vec![0u8; ((glyphs.len() + 31) >> 5) << 2];
+        let mut explicit = true;
+        for (i, (num_contours, bbox, data)) in glyphs.iter().enumerate() {
+            contours.extend_from_slice(&num_contours.to_be_bytes());
+            let write_bbox = *num_contours < 0 || (*num_contours > 0 && explicit);
+            if write_bbox {
+                bbox_bitmap[i >> 3] |= 0x80 >> (i & 7);
+                for value in bbox {
+                    bboxes.extend_from_slice(&value.to_be_bytes());
+                }
+            }
+            if *num_contours < 0 {
+                composites.extend_from_slice(&data[10..]);
+            } else if *num_contours > 0 {
+                explicit = false;
+                let mut s = Stream::new(&data[10..]);
+                let mut last = 0;
+                for _ in 0..*num_contours {
+                    let end = s.u16().unwrap() + 1;
+                    write_u255(points, end - last);
+                    last = end;
+                }
+                let instruction_len = s.u16().unwrap();
+                instructions.extend_from_slice(s.bytes(instruction_len as usize).unwrap());
+                let point_flags: Vec<_> = (0..last).map(|_| s.u8().unwrap()).collect();
+                let mut deltas = vec![(0i32, 0i32); point_flags.len()];
+                for (axis, (short, same)) in [(0x02, 0x10), (0x04, 0x20)].iter().enumerate() {
+                    for (flag, delta) in point_flags.iter().zip(&mut deltas) {
+                        let value = match (flag & short != 0, flag & same != 0) {
+                            (true, positive) => {
+                                let value = s.u8().unwrap() as i32;
+                                if positive {
+                                    value
+                                } else {
+                                    -value
+                                }
+                            }
+                            (false, false) => s.i16().unwrap() as i32,
+                            (false, true) => 0,
+                        };
+                        if axis == 0 {
+                            delta.0 = value;
+                        } else {
+                            delta.1 = value;
+                        }
+                    }
+                }
+                for (flag, (dx, dy)) in point_flags.iter().zip(deltas) {
+                    let off_curve = if flag & 1 == 0 { 0x80 } else { 0 };
+                    let (ax, ay) = (dx.unsigned_abs(), dy.unsigned_abs());
+                    let triplet = if dx == 0 && ay < 1280 {
+                        coords.push(ay as u8);
+                        ((ay >> 8) << 1) as u8 | (dy > 0) as u8
+                    } else if dy == 0 && ax < 1280 {
+                        coords.push(ax as u8);
+                        10 + ((ax >> 8) << 1) as u8 | (dx > 0) as u8
+                    } else {
+                        coords.extend_from_slice(&(ax as u16).to_be_bytes());
+                        coords.extend_from_slice(&(ay as u16).to_be_bytes());
+                        124 + (dx > 0) as u8 + ((dy > 0) as u8) * 2
+                    };
+                    flags.push(triplet | off_curve);
+                }
+                write_u255(coords, instruction_len);
+            }
+        }
+        bbox_bitmap.extend_from_slice(bboxes);
+        *bboxes = bbox_bitmap;
+        let mut transformed = Vec::new();
+        for value in [0, 0, glyphs.len() as u16, 1] {
+            transformed.extend_from_slice(&value.to_be_bytes());
+        }
+        for stream in &streams {
+            transformed.extend_from_slice(&(stream.len() as u32).to_be_bytes());
+        }
+        for stream in &streams {
+            transformed.extend_from_slice(stream);
+        }
+        transformed
+    }
+
+    fn write_u255(data: &mut Vec<u8>, value: u16) {
+        if value < 253 {
+            data.push(value as u8);
+        } else {
+            data.push(253);
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+
+    fn write_base128(data: &mut Vec<u8>, value: u32) {
+        let len = (1..5).find(|i| value >> (7 * i) == 0).unwrap_or(5);
+        for i in (0..len).rev() {
+            let byte = (value >> (7 * i)) as u8 & 0x7F;
+            data.push(if i == 0 { byte } else { byte | 0x80 });
+        }
+    }
+
+    /// Writes a brotli stream with uncompressed meta-blocks.
+    fn brotli_uncompressed(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        // A window of 2^24 bytes.
+        let (mut header, mut shift) = (0b1111, 4);
+        for chunk in data.chunks(0x10000) {
+            // Not last, four nibbles of length and uncompressed, followed by
+            // padding to the next byte.
+            let bits: u32 = header | (chunk.len() as u32 - 1) << (shift + 3) | 1 << (shift + 19);
+            out.extend_from_slice(&bits.to_le_bytes()[..3]);
+            out.extend_from_slice(chunk);
+            (header, shift) = (0, 0);
+        }
+        // Last and empty.
+        out.push(0b11);
+        out
+    }
+
+    /// Asserts that the decoded data has the fonts with the specified
+    /// tables.
+    fn assert_fonts(data: &[u8], fonts: &[Tables]) {
+        let decoded = FontDataRef::new(data).unwrap();
+        assert_eq!(decoded.len(), fonts.len());
+        for (i, tables) in fonts.iter().enumerate() {
+            let font = decoded.get(i).unwrap();
+            for (tag, table) in tables {
+                let decoded = font.table_by_tag(swash::tag_from_bytes(tag)).unwrap();
+                assert_eq!(decoded, &table[..], "{}", String::from_utf8_lossy(tag));
+            }
+            assert_ne!(font.charmap().map('À'), 0);
+        }
+    }
+
+    #[test]
+    fn woff_round_trip() {
+        let tables = test_font("Woff").sfnt_tables();
+        let decoded = decode(&test_font::woff(&tables)).unwrap();
+        assert_eq!(decoded, test_font::sfnt(&tables));
+        assert_fonts(&decoded, &[tables]);
+    }
+
+    #[test]
+    fn woff2_round_trip() {
+        let tables = test_font("Woff2").sfnt_tables();
+        let decoded = decode(&woff2(&[tables.clone()])).unwrap();
+        assert_fonts(&decoded, &[tables]);
+    }
+
+    #[test]
+    fn woff2_collection() {
+        let regular = test_font("Woff2").sfnt_tables();
+        let bold = TestFont {
+            subfamily: "Bold".into(),
+            weight: 700,
+            ..test_font("Woff2")
+        }
+        .sfnt_tables();
+        let data = woff2(&[regular.clone(), bold.clone()]);
+        let decoded = decode(&data).unwrap();
+        assert_fonts(&decoded, &[regular, bold]);
+    }
+
+    #[test]
+    fn malformed() {
+        let tables = test_font("Malformed").sfnt_tables();
+        let woff = test_font::woff(&tables);
+        let woff2 = woff2(&[tables.clone(), tables]);
+        for data in [&woff, &woff2] {
+            assert!(decode(&data[..data.len() / 2]).is_none());
+            for len in 0..data.len() {
+                let _ = decode(&data[..len]);
+            }
+            let mut data = data.clone();
+            for i in 0..data.len() {
+                for value in [0, 0x7F, 0xFF] {
+                    let byte = data[i];
+                    data[i] = value;
+                    let _ = decode(&data);
+                    data[i] = byte;
+                }
+            }
+        }
+        assert!(decode(b"wOF2").is_none());
+        assert!(decode(b"OTTO").is_none());
+    }
+
+    #[test]
+    fn short_loca_overflow() {
+        // Three single point glyphs with maximal instructions overflow short
+        // loca offsets.
+        let transformed = |index_format: u16| {
+            let mut streams = [(); 7].map(|_| Vec::new());
+            let [contours, points, flags, coords, _, bboxes, instructions] = &mut streams;
+            for _ in 0..3 {
+                contours.extend_from_slice(&1i16.to_be_bytes());
+                write_u255(points, 1);
+                flags.push(0);
+                coords.push(0);
+                write_u255(coords, 0xFFFF);
+                instructions.resize(instructions.len() + 0xFFFF, 0);
+            }
+            bboxes.resize(4, 0);
+            let mut data = Vec::new();
+            for value in [0, 0, 3, index_format] {
+                data.extend_from_slice(&value.to_be_bytes());
+            }
+            for stream in &streams {
+                data.extend_from_slice(&(stream.len() as u32).to_be_bytes());
+            }
+            for stream in &streams {
+                data.extend_from_slice(stream);
+            }
+            data
+        };
+        assert!(reconstruct_glyf(&transformed(0)).is_none());
+        let glyf = reconstruct_glyf(&transformed(1)).unwrap();
+        assert!(glyf.glyf.len() > 0x1FFFE);
+        assert_eq!(glyf.loca.len(), 16);
+    }
+}